@@ -0,0 +1,41 @@
+use rand::{rngs::StdRng, seq::index, Rng, SeedableRng};
+
+use crate::Cell;
+
+// Makes garbage rows, each filled but for a few holes. Messiness is the chance, from 0 to 1, that
+// a row moves its holes instead of lining them up with the row below it.
+#[derive(Debug)]
+pub struct GarbageGenerator {
+    rng: StdRng,
+    width: usize,
+    holes: usize,
+    messiness: f64,
+    hole_columns: Vec<usize>,
+}
+
+impl GarbageGenerator {
+    pub fn new(seed: u64, width: usize, holes: usize, messiness: f64) -> GarbageGenerator {
+        GarbageGenerator {
+            rng: StdRng::seed_from_u64(seed),
+            width,
+            holes,
+            messiness,
+            hole_columns: Vec::new(),
+        }
+    }
+
+    pub fn next_row(&mut self) -> Vec<Cell> {
+        if self.hole_columns.is_empty() || self.rng.gen_bool(self.messiness) {
+            self.hole_columns = index::sample(&mut self.rng, self.width, self.holes).into_vec();
+        }
+        (0..self.width)
+            .map(|column| {
+                if self.hole_columns.contains(&column) {
+                    Cell::Empty
+                } else {
+                    Cell::Garbage
+                }
+            })
+            .collect()
+    }
+}
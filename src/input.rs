@@ -0,0 +1,240 @@
+use std::time::{Duration, Instant};
+
+use crate::{Button, Event};
+
+// The terminal only reports key presses, never releases, and repeats a held key on its own. A key
+// is held while it keeps repeating, which comes much faster than anyone can tap a key. Terminals
+// repeat a held key at least this often, so a key that is quiet for this long was let go.
+pub const KEY_REPEAT_INTERVAL: Duration = Duration::from_millis(75);
+// Terminals start repeating a held key somewhere in this range after it was pressed. A key that
+// comes again in this range may be starting to repeat, or may have been tapped again.
+const MIN_KEY_REPEAT_DELAY: Duration = Duration::from_millis(200);
+const MAX_KEY_REPEAT_DELAY: Duration = Duration::from_millis(1000);
+// How far the first repeat of a key can be from the repeat delay measured on an earlier key
+const KEY_REPEAT_DELAY_TOLERANCE: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy)]
+enum KeyState {
+    // Pressed and let go right away, when it was last tapped
+    Tapped(Instant),
+    // Came again when it could be starting to repeat. It is held if it comes yet again right
+    // away, otherwise it was tapped again
+    Pending { tapped: Instant, seen: Instant },
+    // Repeating, when it was last seen
+    Held(Instant),
+}
+
+// Turns the key presses of a terminal into presses, repeats and releases of buttons. Every tap
+// acts right away, only a key that could be starting to repeat waits to see whether it does.
+#[derive(Debug, Default)]
+pub struct KeyTracker {
+    keys: Vec<(Button, KeyState)>,
+    // How long the terminal takes to start repeating a held key, once a key was seen doing it
+    repeat_delay: Option<Duration>,
+}
+
+impl KeyTracker {
+    pub fn new() -> KeyTracker {
+        KeyTracker::default()
+    }
+
+    // The events for the key of a button coming in at `now`
+    pub fn key(&mut self, button: Button, now: Instant) -> Vec<Event> {
+        let mut events = self.update(now);
+        let index = self.keys.iter().position(|(b, _)| *b == button);
+        let state = match index {
+            Some(index) => match self.keys[index].1 {
+                KeyState::Held(_) => KeyState::Held(now),
+                KeyState::Pending { tapped, seen } => {
+                    // Coming again right away, so the key is repeating
+                    self.repeat_delay = Some(seen.duration_since(tapped));
+                    events.push(Event::Repeat(button));
+                    KeyState::Held(now)
+                }
+                KeyState::Tapped(tapped) => {
+                    let since_tapped = now.duration_since(tapped);
+                    if self.could_start_repeating(since_tapped) {
+                        KeyState::Pending { tapped, seen: now }
+                    } else if since_tapped < KEY_REPEAT_INTERVAL {
+                        events.push(Event::Repeat(button));
+                        KeyState::Held(now)
+                    } else {
+                        tap(&mut events, button);
+                        KeyState::Tapped(now)
+                    }
+                }
+            },
+            None => {
+                tap(&mut events, button);
+                KeyState::Tapped(now)
+            }
+        };
+        match index {
+            Some(index) => self.keys[index].1 = state,
+            None => self.keys.push((button, state)),
+        }
+        events
+    }
+
+    // The events for the time passing until `now`, releasing the keys that stopped repeating and
+    // settling the ones that didn't start
+    pub fn update(&mut self, now: Instant) -> Vec<Event> {
+        let mut events = Vec::new();
+        self.keys.retain_mut(|(button, state)| match *state {
+            KeyState::Held(seen) => {
+                let held = now.duration_since(seen) < KEY_REPEAT_INTERVAL;
+                if !held {
+                    events.push(Event::Release(*button));
+                }
+                held
+            }
+            KeyState::Pending { seen, .. } => {
+                if now.duration_since(seen) >= KEY_REPEAT_INTERVAL {
+                    tap(&mut events, *button);
+                    *state = KeyState::Tapped(seen);
+                }
+                true
+            }
+            KeyState::Tapped(tapped) => now.duration_since(tapped) <= MAX_KEY_REPEAT_DELAY,
+        });
+        events
+    }
+
+    fn could_start_repeating(&self, since_tapped: Duration) -> bool {
+        match self.repeat_delay {
+            Some(delay) => {
+                since_tapped.max(delay) - since_tapped.min(delay) <= KEY_REPEAT_DELAY_TOLERANCE
+            }
+            None => (MIN_KEY_REPEAT_DELAY..=MAX_KEY_REPEAT_DELAY).contains(&since_tapped),
+        }
+    }
+}
+
+// Press a button and let go of it right away
+fn tap(events: &mut Vec<Event>, button: Button) {
+    events.push(Event::Press(button));
+    events.push(Event::Release(button));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CLOCKWISE;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    // Feed the keys of a button at the given times, checking for released keys in between the way
+    // the input thread does, and describe the events that came out
+    fn feed(
+        tracker: &mut KeyTracker,
+        button: Button,
+        start: Instant,
+        times: &[u64],
+        until: u64,
+    ) -> Vec<String> {
+        let mut events = Vec::new();
+        let mut times = times.iter().peekable();
+        for now in (0..=until).step_by(5) {
+            events.extend(tracker.update(start + ms(now)));
+            if times.peek() == Some(&&now) {
+                times.next();
+                events.extend(tracker.key(button, start + ms(now)));
+            }
+        }
+        events.iter().map(|event| format!("{:?}", event)).collect()
+    }
+
+    fn taps(button: Button, count: usize) -> Vec<String> {
+        let press = format!("{:?}", Event::Press(button));
+        let release = format!("{:?}", Event::Release(button));
+        [press, release]
+            .iter()
+            .cycle()
+            .take(2 * count)
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn rapid_taps_each_act_right_away() {
+        let times = [0, 100, 200];
+        let mut tracker = KeyTracker::new();
+        let start = Instant::now();
+        for (tap, now) in times.into_iter().enumerate() {
+            let events = tracker.key(Button::Left, start + ms(now));
+            assert_eq!(events.len(), 2, "tap {} acted late", tap);
+        }
+        let events = feed(&mut KeyTracker::new(), Button::Left, start, &times, 2000);
+        assert_eq!(events, taps(Button::Left, 3));
+    }
+
+    #[test]
+    fn rapid_rotate_taps_each_rotate_and_a_held_one_only_once() {
+        let rotate = Button::Rotate(CLOCKWISE);
+        let events = feed(
+            &mut KeyTracker::new(),
+            rotate,
+            Instant::now(),
+            &[0, 100, 200],
+            2000,
+        );
+        assert_eq!(events, taps(rotate, 3));
+        // Held through the next spawn for IRS, which repeating must not turn into more rotations
+        let times: Vec<u64> = [0].into_iter().chain((500..=800).step_by(30)).collect();
+        let events = feed(&mut KeyTracker::new(), rotate, Instant::now(), &times, 2000);
+        let press_count = events.iter().filter(|e| e.starts_with("Press")).count();
+        assert_eq!(press_count, 1);
+        assert!(events.iter().any(|e| e.starts_with("Repeat")));
+    }
+
+    #[test]
+    fn slow_taps_are_not_taken_for_a_held_key() {
+        let start = Instant::now();
+        let events = feed(
+            &mut KeyTracker::new(),
+            Button::Left,
+            start,
+            &[0, 400, 800],
+            2000,
+        );
+        assert_eq!(events, taps(Button::Left, 3));
+    }
+
+    #[test]
+    fn a_repeating_key_is_held_until_it_stops_repeating() {
+        let times: Vec<u64> = [0].into_iter().chain((500..=800).step_by(30)).collect();
+        let events = feed(
+            &mut KeyTracker::new(),
+            Button::Left,
+            Instant::now(),
+            &times,
+            2000,
+        );
+        assert_eq!(
+            events,
+            [
+                "Press(Left)",
+                "Release(Left)",
+                "Repeat(Left)",
+                "Release(Left)"
+            ]
+        );
+    }
+
+    #[test]
+    fn taps_only_wait_around_the_measured_repeat_delay() {
+        let mut tracker = KeyTracker::new();
+        let times: Vec<u64> = [0].into_iter().chain((500..=600).step_by(30)).collect();
+        let start = Instant::now();
+        feed(&mut tracker, Button::Right, start, &times, 1000);
+        let start = start + ms(2000);
+        assert_eq!(tracker.key(Button::Right, start).len(), 2);
+        // Too far from the terminal's repeat delay to be a repeat
+        assert_eq!(tracker.key(Button::Right, start + ms(300)).len(), 2);
+        // Could be the first repeat, so it waits for the next one
+        assert!(tracker.key(Button::Right, start + ms(800)).is_empty());
+        assert_eq!(tracker.update(start + ms(900)).len(), 2);
+    }
+}
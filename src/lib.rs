@@ -1,24 +1,34 @@
-use rand::seq::SliceRandom;
 use std::{
     error::{self, Error},
     fmt, io,
-    sync::mpsc::{self, Receiver, Sender},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
     thread::{self, sleep},
     time::{Duration, Instant},
 };
 use termion::{event::Key, input::TermRead};
 
+mod garbage;
+mod input;
+mod randomizer;
+pub use garbage::GarbageGenerator;
+use input::{KeyTracker, KEY_REPEAT_INTERVAL};
+pub use randomizer::{
+    parse_piece_sequence, BagRandomizer, NesRandomizer, PieceQueue, PureRandomizer, Randomizer,
+    RandomizerKind, SequenceRandomizer, Tgm3Randomizer, TgmHistoryRandomizer,
+};
+
 use termion::raw::RawTerminal;
 use tui::{
     backend::TermionBackend,
     layout::{Alignment, Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, Cell, Paragraph, Row, Table, Wrap},
+    widgets::{Block, Borders, Cell as TableCell, Gauge, Paragraph, Row, Table, Wrap},
     Terminal,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum TetrisDirection {
     Up,
     Down,
@@ -39,23 +49,209 @@ pub const DOWN: TetrisDirection = TetrisDirection::Down;
 pub const LEFT: TetrisDirection = TetrisDirection::Left;
 pub const RIGHT: TetrisDirection = TetrisDirection::Right;
 
-pub const KICKS: [(i16, i16); 5] = [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)];
+// SRS wall kick data for the J, L, S, T and Z pieces, indexed by the rotation state the piece is
+// rotating from. Offsets use the guideline's (x, y) notation, where positive y points up.
+pub const JLSTZ_CLOCKWISE_KICKS: [[(i16, i16); 5]; 4] = [
+    // 0 -> R
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    // R -> 2
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    // 2 -> L
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    // L -> 0
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+pub const JLSTZ_COUNTER_CLOCKWISE_KICKS: [[(i16, i16); 5]; 4] = [
+    // 0 -> L
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    // R -> 0
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    // 2 -> R
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    // L -> 2
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+// SRS wall kick data for the I piece, in the same layout as the JLSTZ tables
+pub const I_CLOCKWISE_KICKS: [[(i16, i16); 5]; 4] = [
+    // 0 -> R
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    // R -> 2
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    // 2 -> L
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    // L -> 0
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+];
+pub const I_COUNTER_CLOCKWISE_KICKS: [[(i16, i16); 5]; 4] = [
+    // 0 -> L
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    // R -> 0
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    // 2 -> R
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    // L -> 2
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+];
+// SRS+ wall kick data for half turns, as used by TETR.IO for every piece but O. Indexed and
+// laid out like the quarter turn tables.
+pub const HALF_TURN_KICKS: [[(i16, i16); 6]; 4] = [
+    // 0 -> 2
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+    // R -> L
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+    // 2 -> 0
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+    // L -> R
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+];
+// Rotating into place with the last kick of the JLSTZ tables turns a mini T-spin into a full one
+const T_SPIN_UPGRADE_KICK: usize = 4;
+// The O piece never kicks
+pub const O_KICKS: [(i16, i16); 1] = [(0, 0)];
 
-#[derive(Debug)]
+pub const PLAYFIELD_WIDTH: usize = 10;
+// How many upcoming pieces can be previewed
+pub const MAX_PREVIEW: usize = 6;
+pub const VISIBLE_HEIGHT: usize = 20;
+// Only the bottom rows of the matrix are visible. Above them there is a buffer zone this tall for
+// pieces spawning, kicking or being stacked above the skyline
+pub const BUFFER_HEIGHT: usize = 20;
+// Pieces spawn in the two rows right above the skyline, with this being the row of their center
+const SPAWN_ROW: i16 = BUFFER_HEIGHT as i16 - 1;
+// The narrowest playfield where every piece can spawn
+const MIN_PLAYFIELD_WIDTH: usize = 4;
+
+// The game advances in frames, and gravity is measured in cells per frame
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAMES_PER_SECOND as u64);
+// Gravity this strong or stronger drops pieces to the bottom right away, no matter how tall the
+// playfield is
+pub const INSTANT_GRAVITY: f64 = 20.0;
+
+// The guideline gravity curve: a level takes (0.8 - (level - 1) * 0.007)^(level - 1) seconds per
+// row, reaching 20G at level 19
+pub fn guideline_gravity_table() -> Vec<f64> {
+    (1..=20)
+        .map(|level: i32| {
+            let seconds_per_row = (0.8 - (level - 1) as f64 * 0.007).powi(level - 1);
+            (1.0 / (seconds_per_row * FRAMES_PER_SECOND as f64)).min(INSTANT_GRAVITY)
+        })
+        .collect()
+}
+
+// Parse a comma separated list of gravities in cells per frame, one per level starting from
+// level 1, such as "1/60,0.5,1G,20G"
+pub fn parse_gravity_table(s: &str) -> Result<Vec<f64>, String> {
+    s.split(',')
+        .map(|entry| {
+            let entry = entry.trim();
+            let number = entry.strip_suffix(['G', 'g']).unwrap_or(entry);
+            let gravity = match number.split_once('/') {
+                Some((numerator, denominator)) => numerator
+                    .parse::<f64>()
+                    .and_then(|numerator| Ok(numerator / denominator.parse::<f64>()?)),
+                None => number.parse(),
+            }
+            .map_err(|_| format!("Invalid gravity: {}", entry))?;
+            if !gravity.is_finite() || gravity < 0.0 {
+                return Err(format!("Invalid gravity: {}", entry));
+            }
+            Ok(gravity)
+        })
+        .collect()
+}
+
+// In frames
+pub const LOCK_DELAY: u32 = 30;
+// How many frames pass between a piece locking and the next one spawning (ARE)
+pub const ENTRY_DELAY: u32 = 0;
+// How many frames filled rows stay on the playfield before being cleared, on top of the entry delay
+pub const LINE_CLEAR_DELAY: u32 = 0;
+// How many frames a direction has to be held before it starts repeating (Delayed Auto Shift)
+pub const DAS: u32 = 10;
+// How many frames pass between repeated moves once DAS is charged, with 0 moving straight to the
+// wall (Auto Repeat Rate)
+pub const ARR: u32 = 2;
+// How many times stronger than gravity soft dropping is
+pub const SOFT_DROP_FACTOR: u32 = 20;
+pub const COMBO_POINTS: u64 = 50;
+pub const SOFT_DROP_POINTS_PER_CELL: u64 = 1;
+pub const HARD_DROP_POINTS_PER_CELL: u64 = 2;
+
+// How many times moving or rotating a piece can restart its lock delay with the move reset policy
+pub const MOVE_RESET_LIMIT: u32 = 15;
+
+// What restarts the lock delay of a piece resting on the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockDelayPolicy {
+    // Moving or rotating restarts it, up to `MOVE_RESET_LIMIT` times. Reaching a row lower than
+    // ever before gives all the resets back
+    MoveReset,
+    // Only moving down to a row lower than ever before restarts it
+    StepReset,
+    // It starts when the piece first lands and never restarts
+    NoReset,
+}
+
+impl FromStr for LockDelayPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "move" => Ok(LockDelayPolicy::MoveReset),
+            "step" => Ok(LockDelayPolicy::StepReset),
+            "classic" => Ok(LockDelayPolicy::NoReset),
+            _ => Err(format!("Unknown lock delay policy: {}", s)),
+        }
+    }
+}
+
+// The lines needed to leave a level with the fixed goal
+pub const FIXED_GOAL_LINES: u32 = 10;
+// With the variable goal, leaving a level needs this many lines per level number
+pub const VARIABLE_GOAL_LINES_PER_LEVEL: u32 = 5;
+
+// How the lines needed to reach the next level are counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelGoal {
+    // Every level needs 5 lines per level number, and clearing several lines at once is worth
+    // more: 1, 3, 5 and 8 lines for a single, double, triple and tetris, and up to 16 for a T-spin
+    // triple. Back-to-back clears are worth half as much again.
+    Variable,
+    // Every level needs `FIXED_GOAL_LINES` actual lines
+    Fixed,
+}
+
+impl FromStr for LevelGoal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "variable" => Ok(LevelGoal::Variable),
+            "fixed" => Ok(LevelGoal::Fixed),
+            _ => Err(format!("Unknown level goal: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Rotation {
     Clockwise,
     CounterClockwise,
+    HalfTurn,
 }
 impl Rotation {
     pub fn other_direction(&self) -> Rotation {
         match self {
             Rotation::Clockwise => Rotation::CounterClockwise,
             Rotation::CounterClockwise => Rotation::Clockwise,
+            Rotation::HalfTurn => Rotation::HalfTurn,
         }
     }
 }
 pub const COUNTER_CLOCKWISE: Rotation = Rotation::CounterClockwise;
 pub const CLOCKWISE: Rotation = Rotation::Clockwise;
+pub const HALF_TURN: Rotation = Rotation::HalfTurn;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Point(pub i16, pub i16);
@@ -92,18 +288,198 @@ pub enum MinoesError {
     OverlappingMinoes(OverlappingMinoesError),
 }
 
+// The ways a game can end: topping out as defined by the guideline, or reaching the end of the
+// game mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameOverReason {
+    // A new piece spawned overlapping the stack
+    BlockOut,
+    // A piece locked entirely above the skyline
+    LockOut,
+    // A piece locked partly above the skyline, only when enabled in the game config
+    PartialLockOut,
+    // Garbage pushed the stack out through the top of the buffer zone
+    TopOut,
+    // The lines or levels the game mode asks for were cleared
+    GoalReached,
+    // The time limit of the game mode ran out
+    TimeUp,
+}
+impl GameOverReason {
+    pub fn is_top_out(&self) -> bool {
+        matches!(
+            self,
+            GameOverReason::BlockOut
+                | GameOverReason::LockOut
+                | GameOverReason::PartialLockOut
+                | GameOverReason::TopOut
+        )
+    }
+}
+impl fmt::Display for GameOverReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameOverReason::BlockOut => write!(f, "Block out"),
+            GameOverReason::LockOut => write!(f, "Lock out"),
+            GameOverReason::PartialLockOut => write!(f, "Partial lock out"),
+            GameOverReason::TopOut => write!(f, "Top out"),
+            GameOverReason::GoalReached => write!(f, "Goal reached"),
+            GameOverReason::TimeUp => write!(f, "Time up"),
+        }
+    }
+}
+
+// Marathon ends once this many lines are cleared, and levels stop going up past the last one
+pub const MARATHON_LINES: u32 = 150;
+pub const MARATHON_LEVELS: u32 = 15;
+pub const SPRINT_LINES: u32 = 40;
+// How many garbage rows there are to dig through by default
+pub const DIG_LINES: u32 = 18;
+// Dig mode keeps up to this many garbage rows on the playfield, and never more than half of its
+// visible rows
+pub const GARBAGE_HEIGHT: usize = 10;
+// Mixed into the game seed for the garbage rows, so their holes don't follow the piece sequence
+const GARBAGE_SEED_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
+// In frames, which makes 2 minutes
+pub const ULTRA_FRAMES: u64 = 2 * 60 * FRAMES_PER_SECOND as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    // Clear `MARATHON_LINES` lines, going up to level `MARATHON_LEVELS`, always with the fixed goal
+    Marathon,
+    // Clear `SPRINT_LINES` lines as fast as possible, at level 1
+    Sprint,
+    // Score as much as possible in `ULTRA_FRAMES` frames, at level 1
+    Ultra,
+    // Play for as long as wanted: instead of topping out, the stack vanishes
+    Endless,
+    // Dig through garbage rows as fast as possible, at level 1
+    Dig,
+}
+
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "marathon" => Ok(GameMode::Marathon),
+            "sprint" => Ok(GameMode::Sprint),
+            "ultra" => Ok(GameMode::Ultra),
+            "endless" | "zen" => Ok(GameMode::Endless),
+            "dig" | "cheese" => Ok(GameMode::Dig),
+            _ => Err(format!("Unknown game mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameMode::Marathon => write!(f, "Marathon"),
+            GameMode::Sprint => write!(f, "Sprint"),
+            GameMode::Ultra => write!(f, "Ultra"),
+            GameMode::Endless => write!(f, "Endless"),
+            GameMode::Dig => write!(f, "Dig"),
+        }
+    }
+}
+
+// Show a number of frames as minutes, seconds and hundredths of a second
+pub fn format_frames(frames: u64) -> String {
+    let hundredths = frames * 100 / FRAMES_PER_SECOND as u64;
+    format!(
+        "{}:{:02}.{:02}",
+        hundredths / 6000,
+        hundredths / 100 % 60,
+        hundredths % 100
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TSpin {
+    Mini,
+    Full,
+}
+
+// What locking a piece achieved: the lines it cleared and whether it was a T-spin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineClear {
+    pub lines: usize,
+    pub t_spin: Option<TSpin>,
+    // The clear left the playfield completely empty
+    pub perfect_clear: bool,
+}
+impl LineClear {
+    // Points for the clear at level 1, following the guideline
+    pub fn base_score(&self) -> u64 {
+        match (self.t_spin, self.lines) {
+            (None, 1) => 100,
+            (None, 2) => 300,
+            (None, 3) => 500,
+            (None, 4) => 800,
+            (Some(TSpin::Mini), 0) => 100,
+            (Some(TSpin::Mini), 1) => 200,
+            (Some(TSpin::Mini), 2) => 400,
+            (Some(TSpin::Full), 0) => 400,
+            (Some(TSpin::Full), 1) => 800,
+            (Some(TSpin::Full), 2) => 1200,
+            (Some(TSpin::Full), 3) => 1600,
+            _ => 0,
+        }
+    }
+
+    // Lines the clear counts for towards the variable level goal, which follow its points
+    pub fn goal_lines(&self) -> u32 {
+        (self.base_score() / 100) as u32
+    }
+
+    // Points for a perfect clear at level 1, on top of the points for the clear itself
+    pub fn perfect_clear_bonus(&self, back_to_back: bool) -> u64 {
+        match self.lines {
+            1 => 800,
+            2 => 1200,
+            3 => 1800,
+            4 if back_to_back => 3200,
+            4 => 2000,
+            _ => 0,
+        }
+    }
+
+    // Tetrises and T-spins that clear lines keep a back-to-back chain going
+    pub fn is_difficult(&self) -> bool {
+        self.lines == 4 || (self.lines > 0 && self.t_spin.is_some())
+    }
+}
+impl fmt::Display for LineClear {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines = match self.lines {
+            0 => "",
+            1 => " Single",
+            2 => " Double",
+            3 => " Triple",
+            _ => " Tetris",
+        };
+        match self.t_spin {
+            Some(TSpin::Mini) => write!(f, "Mini T-Spin{}", lines),
+            Some(TSpin::Full) => write!(f, "T-Spin{}", lines),
+            None => write!(f, "{}", lines.trim_start()),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Piece {
     piece_type: PieceType,
     center: Point,
+    // Index into `rotations`, following the SRS states: 0 (spawn), R, 2 and L
     current_rotation_id: usize,
-    rotations: [[RelPoint; 3]; 4],
-    pub color: i16,
+    rotations: [[RelPoint; 4]; 4],
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq)]
 pub enum PieceType {
     // Clockwise,
+    #[default]
     I,
     J,
     L,
@@ -113,102 +489,242 @@ pub enum PieceType {
     Z,
 }
 
-impl Default for PieceType {
-    fn default() -> PieceType {
-        PieceType::I
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq)]
+pub enum Cell {
+    #[default]
+    Empty,
+    Locked(PieceType),
+    Garbage,
+    // The moving and ghost pieces are never stored in the playfield, they only show up once they
+    // are drawn over it
+    Active(PieceType),
+    Ghost(PieceType),
+    // Drawn over the filled rows while they wait for the line clear delay to end
+    Clearing,
+}
+
+impl Cell {
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Cell::Empty)
     }
 }
 
 impl Piece {
+    // A piece centered on (0, 0), see `spawn` for placing it in a playfield
     pub fn new(piece_type: PieceType) -> Piece {
         let center: Point;
-        let pos_0: [RelPoint; 3];
-        let pos_1: [RelPoint; 3];
-        let pos_2: [RelPoint; 3];
-        let pos_3: [RelPoint; 3];
-        let color: i16;
+        let pos_0: [RelPoint; 4];
+        let pos_r: [RelPoint; 4];
+        let pos_2: [RelPoint; 4];
+        let pos_l: [RelPoint; 4];
+        // Rotation states are the ones from SRS. JLSTZ rotate around their center mino, while the
+        // I piece rotates around a point that is not always one of its minoes
         match piece_type {
             PieceType::I => {
-                center = Point(1, 5);
-                pos_0 = [RelPoint(0, -2), RelPoint(0, -1), RelPoint(0, 1)];
-                pos_1 = [RelPoint(-2, 0), RelPoint(-1, 0), RelPoint(1, 0)];
-                pos_2 = [RelPoint(0, -2), RelPoint(0, -1), RelPoint(0, 1)];
-                pos_3 = [RelPoint(-2, 0), RelPoint(-1, 0), RelPoint(1, 0)];
-                color = 1;
+                center = Point(0, 0);
+                pos_0 = [
+                    RelPoint(0, -2),
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                ];
+                pos_r = [
+                    RelPoint(-1, 0),
+                    RelPoint(0, 0),
+                    RelPoint(1, 0),
+                    RelPoint(2, 0),
+                ];
+                pos_2 = [
+                    RelPoint(1, -2),
+                    RelPoint(1, -1),
+                    RelPoint(1, 0),
+                    RelPoint(1, 1),
+                ];
+                pos_l = [
+                    RelPoint(-1, -1),
+                    RelPoint(0, -1),
+                    RelPoint(1, -1),
+                    RelPoint(2, -1),
+                ];
             }
             PieceType::J => {
-                center = Point(1, 5);
-                pos_0 = [RelPoint(0, -1), RelPoint(0, 1), RelPoint(-1, 1)];
-                pos_1 = [RelPoint(-1, -1), RelPoint(-1, 0), RelPoint(1, 0)];
-                pos_2 = [RelPoint(1, -1), RelPoint(0, -1), RelPoint(0, 1)];
-                pos_3 = [RelPoint(-1, 0), RelPoint(1, 0), RelPoint(1, 1)];
-                color = 2;
+                center = Point(0, 0);
+                pos_0 = [
+                    RelPoint(-1, -1),
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                ];
+                pos_r = [
+                    RelPoint(-1, 0),
+                    RelPoint(-1, 1),
+                    RelPoint(0, 0),
+                    RelPoint(1, 0),
+                ];
+                pos_2 = [
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                    RelPoint(1, 1),
+                ];
+                pos_l = [
+                    RelPoint(-1, 0),
+                    RelPoint(0, 0),
+                    RelPoint(1, -1),
+                    RelPoint(1, 0),
+                ];
             }
             PieceType::L => {
-                center = Point(1, 4);
-                pos_0 = [RelPoint(-1, -1), RelPoint(0, -1), RelPoint(0, 1)];
-                pos_1 = [RelPoint(-1, 0), RelPoint(1, 0), RelPoint(1, -1)];
-                pos_2 = [RelPoint(0, -1), RelPoint(0, 1), RelPoint(1, 1)];
-                pos_3 = [RelPoint(-1, 1), RelPoint(-1, 0), RelPoint(1, 0)];
-                color = 3;
+                center = Point(0, 0);
+                pos_0 = [
+                    RelPoint(-1, 1),
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                ];
+                pos_r = [
+                    RelPoint(-1, 0),
+                    RelPoint(0, 0),
+                    RelPoint(1, 0),
+                    RelPoint(1, 1),
+                ];
+                pos_2 = [
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                    RelPoint(1, -1),
+                ];
+                pos_l = [
+                    RelPoint(-1, -1),
+                    RelPoint(-1, 0),
+                    RelPoint(0, 0),
+                    RelPoint(1, 0),
+                ];
             }
             PieceType::O => {
-                center = Point(1, 4);
-                pos_0 = [RelPoint(-1, 0), RelPoint(0, 1), RelPoint(-1, 1)];
-                pos_1 = [RelPoint(-1, 0), RelPoint(0, 1), RelPoint(-1, 1)];
-                pos_2 = [RelPoint(-1, 0), RelPoint(0, 1), RelPoint(-1, 1)];
-                pos_3 = [RelPoint(-1, 0), RelPoint(0, 1), RelPoint(-1, 1)];
-                color = 4;
+                center = Point(0, 0);
+                pos_0 = [
+                    RelPoint(-1, 0),
+                    RelPoint(-1, 1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                ];
+                pos_r = pos_0;
+                pos_2 = pos_0;
+                pos_l = pos_0;
             }
             PieceType::S => {
-                center = Point(0, 4);
-                pos_0 = [RelPoint(1, -1), RelPoint(1, 0), RelPoint(0, 1)];
-                pos_1 = [RelPoint(-1, 0), RelPoint(0, 1), RelPoint(1, 1)];
-                pos_2 = [RelPoint(1, -1), RelPoint(1, 0), RelPoint(0, 1)];
-                pos_3 = [RelPoint(-1, 0), RelPoint(0, 1), RelPoint(1, 1)];
-                color = 5;
+                center = Point(0, 0);
+                pos_0 = [
+                    RelPoint(-1, 0),
+                    RelPoint(-1, 1),
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                ];
+                pos_r = [
+                    RelPoint(-1, 0),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                    RelPoint(1, 1),
+                ];
+                pos_2 = [
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                    RelPoint(1, -1),
+                    RelPoint(1, 0),
+                ];
+                pos_l = [
+                    RelPoint(-1, -1),
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(1, 0),
+                ];
             }
             PieceType::T => {
-                center = Point(1, 4);
-                pos_0 = [RelPoint(-1, 0), RelPoint(0, -1), RelPoint(0, 1)];
-                pos_1 = [RelPoint(-1, 0), RelPoint(0, -1), RelPoint(1, 0)];
-                pos_2 = [RelPoint(0, -1), RelPoint(1, 0), RelPoint(0, 1)];
-                pos_3 = [RelPoint(-1, 0), RelPoint(1, 0), RelPoint(0, 1)];
-                color = 6;
+                center = Point(0, 0);
+                pos_0 = [
+                    RelPoint(-1, 0),
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                ];
+                pos_r = [
+                    RelPoint(-1, 0),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                    RelPoint(1, 0),
+                ];
+                pos_2 = [
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                    RelPoint(1, 0),
+                ];
+                pos_l = [
+                    RelPoint(-1, 0),
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(1, 0),
+                ];
             }
             PieceType::Z => {
-                center = Point(0, 4);
-                pos_0 = [RelPoint(0, -1), RelPoint(1, 0), RelPoint(1, 1)];
-                pos_1 = [RelPoint(-1, 1), RelPoint(0, 1), RelPoint(1, 0)];
-                pos_2 = [RelPoint(0, -1), RelPoint(1, 0), RelPoint(1, 1)];
-                pos_3 = [RelPoint(-1, 1), RelPoint(0, 1), RelPoint(1, 0)];
-                color = 7;
+                center = Point(0, 0);
+                pos_0 = [
+                    RelPoint(-1, -1),
+                    RelPoint(-1, 0),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                ];
+                pos_r = [
+                    RelPoint(-1, 1),
+                    RelPoint(0, 0),
+                    RelPoint(0, 1),
+                    RelPoint(1, 0),
+                ];
+                pos_2 = [
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(1, 0),
+                    RelPoint(1, 1),
+                ];
+                pos_l = [
+                    RelPoint(-1, 0),
+                    RelPoint(0, -1),
+                    RelPoint(0, 0),
+                    RelPoint(1, -1),
+                ];
             }
         };
         Piece {
             piece_type,
             center,
             current_rotation_id: 0,
-            rotations: [pos_0, pos_1, pos_2, pos_3],
-            color,
+            rotations: [pos_0, pos_r, pos_2, pos_l],
         }
     }
 
+    // A new piece placed at its spawn position, in the middle of the playfield, rounding to the
+    // left
+    pub fn spawn(piece_type: PieceType, playfield_width: usize) -> Piece {
+        let mut piece = Piece::new(piece_type);
+        let column = match piece_type {
+            PieceType::I => (playfield_width - 4) / 2 + 2,
+            PieceType::O => (playfield_width - 4) / 2 + 1,
+            _ => (playfield_width - 3) / 2 + 1,
+        };
+        piece.center = Point(SPAWN_ROW, column as i16);
+        piece
+    }
+
     pub fn get_piece_points(&self) -> Result<[Point; 4], OutOfBoundsError> {
         let mut points = [Point(0, 0); 4];
         let current_rotation = self.rotations[self.current_rotation_id];
-        for i in 0..3 {
-            let rel_point = current_rotation[i];
-            if self.center.0 as i16 + rel_point.0 < 0 {
+        for (i, rel_point) in current_rotation.iter().enumerate() {
+            if self.center.0 + rel_point.0 < 0 {
                 return Err(OutOfBoundsError);
             }
-            let point = Point(
-                self.center.0 as i16 + rel_point.0,
-                self.center.1 as i16 + rel_point.1,
-            );
-            points[i] = point;
+            points[i] = Point(self.center.0 + rel_point.0, self.center.1 + rel_point.1);
         }
-        points[3] = self.center;
         Ok(points)
     }
 
@@ -235,24 +751,66 @@ impl Piece {
 
     pub fn rotate_piece(&mut self, rotation: &Rotation) {
         match rotation {
-            Rotation::CounterClockwise => {
+            Rotation::Clockwise => {
                 self.current_rotation_id = (self.current_rotation_id + 1) % 4;
             }
-            Rotation::Clockwise => {
-                if self.current_rotation_id == 0 {
-                    // can't do modulo because current_rotation_id is unsigned
-                    self.current_rotation_id = 3;
-                } else {
-                    self.current_rotation_id -= 1;
-                }
+            Rotation::CounterClockwise => {
+                self.current_rotation_id = (self.current_rotation_id + 3) % 4;
+            }
+            Rotation::HalfTurn => {
+                self.current_rotation_id = (self.current_rotation_id + 2) % 4;
+            }
+        }
+    }
+
+    // The wall kicks to try, in order, when rotating from the current rotation state
+    fn kicks(&self, rotation: &Rotation) -> &'static [(i16, i16)] {
+        match (self.piece_type, rotation) {
+            (PieceType::O, _) => &O_KICKS,
+            (_, Rotation::HalfTurn) => &HALF_TURN_KICKS[self.current_rotation_id],
+            (PieceType::I, Rotation::Clockwise) => &I_CLOCKWISE_KICKS[self.current_rotation_id],
+            (PieceType::I, Rotation::CounterClockwise) => {
+                &I_COUNTER_CLOCKWISE_KICKS[self.current_rotation_id]
+            }
+            (_, Rotation::Clockwise) => &JLSTZ_CLOCKWISE_KICKS[self.current_rotation_id],
+            (_, Rotation::CounterClockwise) => {
+                &JLSTZ_COUNTER_CLOCKWISE_KICKS[self.current_rotation_id]
             }
         }
     }
 }
 
+// What the game is doing, with the delays counting the frames they have left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    // A piece is in play
+    Falling,
+    // The rows filled by the last piece are about to be cleared
+    LineClearDelay(u32),
+    // The next piece is about to spawn
+    EntryDelay(u32),
+}
+
+// The buttons whose effect depends on them being held. Directions and soft drop keep acting while
+// held, while rotating or holding acts once, and again on every piece spawning while still held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Left,
+    Right,
+    SoftDrop,
+    Rotate(Rotation),
+    Hold,
+}
+
 #[derive(Debug)]
 pub enum Event {
     TimePassed,
+    // Start and stop holding a button, which the game repeats on its own while it is held
+    Press(Button),
+    Release(Button),
+    // Hold a button down without acting on it again, for a tapped key that turned out to be held.
+    // A direction charges DAS as if it had been held since it was last pressed.
+    Repeat(Button),
     MovePiece(TetrisDirection),
     RotatePiece(Rotation),
     HardDropPiece,
@@ -263,609 +821,1362 @@ pub enum Event {
 
 #[derive(Debug)]
 pub struct Game {
+    // How many difficult clears followed the first one without an easy clear in between
+    pub back_to_back: Option<u32>,
+    // In frames
+    pub arr: u32,
+    // How many frames passed since the last repeated move
+    arr_timer: u32,
+    // How many line clearing locks followed the first one without a lock that cleared nothing
+    pub combo: Option<u32>,
+    // In frames
+    pub das: u32,
+    // How many frames the shifting direction has been held for, up to `das`
+    das_charge: u32,
+    // In frames
+    pub entry_delay: u32,
     pub event_receiver: Receiver<Event>,
+    pub event_sender: Sender<Event>,
+    // How many frames the game has been running for, pauses excluded
+    pub frame: u64,
+    pub game_over: Option<GameOverReason>,
+    // Garbage rows cleared so far
+    pub garbage_cleared: u32,
+    // Garbage rows added to the playfield so far
+    pub garbage_added: u32,
+    garbage_generator: GarbageGenerator,
+    // How many garbage rows dig mode has in total
+    pub dig_lines: u32,
+    // The lines counted towards the goal of the current level
+    pub goal_lines: u32,
+    // The gravity of every level from level 1 on, with the last one used for all higher levels
+    pub gravity_table: Vec<f64>,
+    // The fraction of a cell gravity has moved the piece so far
+    gravity_progress: f64,
     pub ghost_piece: Option<Piece>,
+    // A hard drop that came while no piece was in play, done as soon as the next one spawns
+    hard_drop_buffered: bool,
     pub held_piece: Option<Piece>,
+    hold_held: bool,
     pub hold_used: bool,
-    pub level: u16,
-    pub lines_cleared: u16,
+    // In frames
+    pub lock_delay: u32,
+    // How many frames passed since the lock delay of the moving piece was last (re)started, if
+    // it is running
+    pub lock_delay_elapsed: Option<u32>,
+    pub lock_delay_policy: LockDelayPolicy,
+    pub lock_resets: u32,
+    // The lowest row the moving piece has reached so far
+    lowest_row: i16,
+    // The clear made by the last locked piece, if it cleared lines or was a T-spin
+    pub last_clear: Option<LineClear>,
+    // The last successful rotation and its kick, as long as nothing moved the piece after it
+    last_rotation_kick: Option<(Rotation, usize)>,
+    // The last direction pressed and the frame it was pressed on
+    last_shift_press: Option<(Button, u64)>,
+    pub level: u32,
+    pub level_goal: LevelGoal,
+    left_held: bool,
+    // In frames
+    pub line_clear_delay: u32,
+    // Every line cleared so far
+    pub lines_cleared: u32,
+    pub mode: GameMode,
     pub moving_piece: Piece,
-    pub next_pieces: Vec<Piece>,
-    pub playfield: [[i16; 10]; 22],
-    pub score: u16,
-    pub speed_info_sender: Sender<u64>,
+    pub next_pieces: PieceQueue,
+    pub partial_lock_out: bool,
+    pub paused: bool,
+    pub perfect_clears: u32,
+    pub phase: Phase,
+    // How many upcoming pieces are shown
+    pub preview: usize,
+    right_held: bool,
+    // The last rotation button pressed, if it is still held
+    rotation_held: Option<Rotation>,
+    // The whole matrix, hidden buffer zone included, indexed by row then column
+    pub playfield: Vec<Vec<Cell>>,
+    pub score: u64,
+    // Fully determines the sequence of pieces
+    pub seed: u64,
+    // The direction being auto shifted, which is the last one pressed that is still held
+    shift_button: Option<Button>,
+    pub soft_drop_factor: u32,
+    soft_drop_held: bool,
+    pub visible_height: usize,
+    pub width: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameConfig {
+    // Fully determines the sequence of pieces
+    pub seed: u64,
+    pub randomizer: RandomizerKind,
+    pub width: usize,
+    // Visible rows only, the hidden buffer zone comes on top
+    pub height: usize,
+    // End the game when a piece locks even partly above the skyline
+    pub partial_lock_out: bool,
+    // In frames
+    pub lock_delay: u32,
+    pub lock_delay_policy: LockDelayPolicy,
+    pub level_goal: LevelGoal,
+    // Gravity in cells per frame of every level from level 1 on, with the last one used for all
+    // higher levels
+    pub gravity_table: Vec<f64>,
+    // In frames
+    pub das: u32,
+    // In frames
+    pub arr: u32,
+    pub soft_drop_factor: u32,
+    // How many upcoming pieces are shown, from 1 to `MAX_PREVIEW`
+    pub preview: usize,
+    // In frames
+    pub entry_delay: u32,
+    // In frames
+    pub line_clear_delay: u32,
+    pub mode: GameMode,
+    // How many garbage rows dig mode has in total
+    pub dig_lines: u32,
+    // How many holes each garbage row has
+    pub garbage_holes: usize,
+    // The chance, from 0 to 1, that a garbage row has its holes somewhere else than the one below
+    pub messiness: f64,
+}
+
+impl GameConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width < MIN_PLAYFIELD_WIDTH {
+            return Err(format!(
+                "The playfield must be at least {} columns wide",
+                MIN_PLAYFIELD_WIDTH
+            ));
+        }
+        if self.height == 0 {
+            return Err("The playfield must be at least 1 row tall".to_string());
+        }
+        if let RandomizerKind::Sequence(pieces) = &self.randomizer {
+            if pieces.is_empty() {
+                return Err("The piece sequence is empty".to_string());
+            }
+        }
+        if self.gravity_table.is_empty() {
+            return Err("The gravity table needs at least 1 level".to_string());
+        }
+        if self.soft_drop_factor == 0 {
+            return Err("The soft drop factor must be at least 1".to_string());
+        }
+        if self.dig_lines == 0 {
+            return Err("Dig mode needs at least 1 garbage row".to_string());
+        }
+        if self.mode == GameMode::Dig && self.height < 2 {
+            return Err("Dig mode needs a playfield at least 2 rows tall".to_string());
+        }
+        if self.garbage_holes == 0 || self.garbage_holes >= self.width {
+            return Err(format!(
+                "Garbage rows must have from 1 to {} holes",
+                self.width - 1
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.messiness) {
+            return Err("The messiness must be from 0 to 1".to_string());
+        }
+        if !(1..=MAX_PREVIEW).contains(&self.preview) {
+            return Err(format!(
+                "The preview must show from 1 to {} pieces",
+                MAX_PREVIEW
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> GameConfig {
+        GameConfig {
+            seed: rand::random(),
+            randomizer: RandomizerKind::SevenBag,
+            width: PLAYFIELD_WIDTH,
+            height: VISIBLE_HEIGHT,
+            partial_lock_out: false,
+            lock_delay: LOCK_DELAY,
+            lock_delay_policy: LockDelayPolicy::MoveReset,
+            level_goal: LevelGoal::Variable,
+            gravity_table: guideline_gravity_table(),
+            das: DAS,
+            arr: ARR,
+            soft_drop_factor: SOFT_DROP_FACTOR,
+            preview: MAX_PREVIEW,
+            entry_delay: ENTRY_DELAY,
+            line_clear_delay: LINE_CLEAR_DELAY,
+            mode: GameMode::Marathon,
+            dig_lines: DIG_LINES,
+            garbage_holes: 1,
+            messiness: 1.0,
+        }
+    }
 }
 
 impl Default for Game {
     fn default() -> Game {
-        let (event_sender, event_receiver) = mpsc::channel();
-        let (speed_info_sender, speed_info_receiver) = mpsc::channel::<u64>();
-        let key_sender = event_sender.clone();
-        event_sender
-            .send(Event::TimePassed)
-            .expect("Could not send message");
-
-        thread::spawn(move || {
-            let mut last_action_time = Instant::now();
-            let mut time_per_row = 1000;
-            loop {
-                if let Ok(speed) = speed_info_receiver.try_recv() {
-                    time_per_row = speed;
-                }
-                let elapsed = Instant::now().duration_since(last_action_time);
-                let time_elapsed = elapsed >= Duration::from_millis(time_per_row);
-
-                if time_elapsed {
-                    event_sender
-                        .send(Event::TimePassed)
-                        .expect("Could not send message");
-                    last_action_time = Instant::now();
-                }
+        let mut game = Game::new();
+        game.start_threads();
+        game
+    }
+}
 
-                sleep(Duration::from_millis(10));
+// Let the game know every time a frame passes. Frames are scheduled from a fixed start, so a late
+// wake up is caught up on instead of slowing the game down.
+fn spawn_timer_thread(event_sender: Sender<Event>) {
+    thread::spawn(move || {
+        let start = Instant::now();
+        let mut frames_sent: u32 = 0;
+        loop {
+            let next_frame = start + FRAME_DURATION * (frames_sent + 1);
+            sleep(next_frame.saturating_duration_since(Instant::now()));
+            let due_frames = (start.elapsed().as_nanos() / FRAME_DURATION.as_nanos()) as u32;
+            while frames_sent < due_frames {
+                event_sender
+                    .send(Event::TimePassed)
+                    .expect("Could not send message");
+                frames_sent += 1;
             }
-        });
-
-        thread::spawn(move || {
-            for event in io::stdin().keys() {
-                match event {
-                    Ok(Key::Char('q')) => {
-                        key_sender
-                            .send(Event::Quit)
-                            .expect("Could not send message");
-                    }
-                    Ok(Key::Char('j')) => {
-                        key_sender
-                            .send(Event::MovePiece(TetrisDirection::Down))
-                            .expect("Could not send message");
-                    }
-                    Ok(Key::Char('k')) => {
-                        key_sender
-                            .send(Event::RotatePiece(CLOCKWISE))
-                            .expect("Could not send message");
-                    }
-                    Ok(Key::Char('h')) => {
-                        key_sender
-                            .send(Event::MovePiece(TetrisDirection::Left))
-                            .expect("Could not send message");
-                    }
-                    Ok(Key::Char('l')) => {
-                        key_sender
-                            .send(Event::MovePiece(TetrisDirection::Right))
-                            .expect("Could not send message");
-                    }
-                    Ok(Key::Char('d')) => {
-                        key_sender
-                            .send(Event::HardDropPiece)
-                            .expect("Could not send message");
-                    }
-                    Ok(Key::Char('c')) => {
-                        key_sender
-                            .send(Event::HoldPiece)
-                            .expect("Could not send message");
-                    }
-                    Ok(Key::Char('p')) => {
-                        key_sender
-                            .send(Event::TogglePause)
-                            .expect("Could not send message");
-                    }
+        }
+    });
+}
 
-                    _ => (),
+fn spawn_input_thread(event_sender: Sender<Event>) {
+    let (key_sender, key_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for key in io::stdin().keys().flatten() {
+            if key_sender.send(key).is_err() {
+                break;
+            }
+        }
+    });
+    thread::spawn(move || {
+        let mut key_tracker = KeyTracker::new();
+        loop {
+            let key = key_receiver.recv_timeout(KEY_REPEAT_INTERVAL / 4);
+            let now = Instant::now();
+            let send = |events: Vec<Event>| {
+                for event in events {
+                    event_sender.send(event).expect("Could not send message");
                 }
+            };
+            send(key_tracker.update(now));
+            let event = match key {
+                Ok(Key::Char('q')) => Event::Quit,
+                Ok(Key::Char('j')) => Event::Press(Button::SoftDrop),
+                Ok(Key::Char('k')) => Event::Press(Button::Rotate(CLOCKWISE)),
+                Ok(Key::Char('z')) => Event::Press(Button::Rotate(COUNTER_CLOCKWISE)),
+                Ok(Key::Char('a')) => Event::Press(Button::Rotate(HALF_TURN)),
+                Ok(Key::Char('h')) => Event::Press(Button::Left),
+                Ok(Key::Char('l')) => Event::Press(Button::Right),
+                Ok(Key::Char('d')) => Event::HardDropPiece,
+                Ok(Key::Char('c')) => Event::Press(Button::Hold),
+                Ok(Key::Char('p')) => Event::TogglePause,
+                Err(RecvTimeoutError::Disconnected) => return,
+                _ => continue,
+            };
+            // The terminal can't tell a tap from a held key, the tracker works it out
+            match event {
+                Event::Press(button) => send(key_tracker.key(button, now)),
+                event => send(vec![event]),
             }
-        });
+        }
+    });
+}
 
-        let mut rng = rand::thread_rng();
-        let mut next_pieces = vec![
-            Piece::new(PieceType::I),
-            Piece::new(PieceType::J),
-            Piece::new(PieceType::L),
-            Piece::new(PieceType::O),
-            Piece::new(PieceType::S),
-            Piece::new(PieceType::T),
-            Piece::new(PieceType::Z),
-        ];
-        next_pieces.shuffle(&mut rng);
+impl Game {
+    // A game that spawns no threads at all. Nothing happens unless events are fed to it through
+    // `handle_event` (or the move/rotate/drop methods are called directly), so it can be driven
+    // by tests, bots or other front-ends with their own notion of time.
+    pub fn new() -> Game {
+        Game::with_config(GameConfig::default()).expect("The default config is valid")
+    }
+
+    pub fn with_seed(seed: u64) -> Game {
+        Game::with_config(GameConfig {
+            seed,
+            ..GameConfig::default()
+        })
+        .expect("The default config is valid")
+    }
+
+    pub fn with_config(config: GameConfig) -> Result<Game, String> {
+        config.validate()?;
+        let (event_sender, event_receiver) = mpsc::channel();
+        let mut next_pieces = PieceQueue::new(config.randomizer.build(config.seed), config.preview);
+        let first_piece = Piece::spawn(next_pieces.pop(), config.width);
 
-        return Self {
+        let mut game = Self {
+            arr: config.arr,
+            arr_timer: 0,
+            back_to_back: None,
+            combo: None,
+            das: config.das,
+            das_charge: 0,
+            entry_delay: config.entry_delay,
             event_receiver,
+            event_sender,
+            frame: 0,
+            game_over: None,
+            garbage_cleared: 0,
+            garbage_added: 0,
+            garbage_generator: GarbageGenerator::new(
+                config.seed ^ GARBAGE_SEED_SALT,
+                config.width,
+                config.garbage_holes,
+                config.messiness,
+            ),
+            dig_lines: config.dig_lines,
+            goal_lines: 0,
+            gravity_table: config.gravity_table,
+            gravity_progress: 0.0,
             ghost_piece: None,
+            hard_drop_buffered: false,
             held_piece: None,
+            hold_held: false,
             hold_used: false,
+            lock_delay: config.lock_delay,
+            lock_delay_elapsed: None,
+            lock_delay_policy: config.lock_delay_policy,
+            lock_resets: 0,
+            lowest_row: 0,
+            last_clear: None,
+            last_rotation_kick: None,
+            last_shift_press: None,
             level: 1,
+            // 15 levels only add up to 150 lines with 10 lines each, the variable goal would need
+            // 600 goal lines, more than even 150 lines of tetrises give
+            level_goal: match config.mode {
+                GameMode::Marathon => LevelGoal::Fixed,
+                _ => config.level_goal,
+            },
+            left_held: false,
+            line_clear_delay: config.line_clear_delay,
             lines_cleared: 0,
-            moving_piece: next_pieces.pop().unwrap(),
+            mode: config.mode,
+            moving_piece: first_piece,
             next_pieces,
-            playfield: [[0; 10]; 22],
+            partial_lock_out: config.partial_lock_out,
+            paused: false,
+            perfect_clears: 0,
+            phase: Phase::Falling,
+            preview: config.preview,
+            right_held: false,
+            rotation_held: None,
+            playfield: vec![vec![Cell::Empty; config.width]; config.height + BUFFER_HEIGHT],
             score: 0,
-            speed_info_sender,
+            seed: config.seed,
+            shift_button: None,
+            soft_drop_factor: config.soft_drop_factor,
+            soft_drop_held: false,
+            visible_height: config.height,
+            width: config.width,
         };
+        if game.mode == GameMode::Dig {
+            game.add_garbage()
+                .map_err(|reason| format!("The garbage doesn't fit: {}", reason))?;
+        }
+        game.add_piece_to_field(first_piece)
+            .map_err(|_| "The first piece doesn't fit in the playfield".to_string())?;
+        Ok(game)
     }
-}
-
-impl Game {
-    pub fn get_next_piece_in_queue(&mut self, pop: bool) -> Piece {
-        let mut next_bag = Vec::new();
-        if self.next_pieces.last().is_none() {
-            let mut rng = rand::thread_rng();
-            next_bag = vec![
-                Piece::new(PieceType::I),
-                Piece::new(PieceType::J),
-                Piece::new(PieceType::L),
-                Piece::new(PieceType::O),
-                Piece::new(PieceType::S),
-                Piece::new(PieceType::T),
-                Piece::new(PieceType::Z),
-            ];
-
-            next_bag.shuffle(&mut rng);
-        }
-        self.next_pieces.append(&mut next_bag);
 
-        if pop {
-            self.next_pieces.pop().unwrap()
-        } else {
-            self.next_pieces.last().unwrap().clone()
-        }
+    // Drive the game from stdin and a gravity timer, sending their events to `event_receiver`
+    pub fn start_threads(&mut self) {
+        spawn_timer_thread(self.event_sender.clone());
+        spawn_input_thread(self.event_sender.clone());
     }
 
-    fn fill_piece_points(&mut self, piece: &Piece) -> Result<(), MinoesError> {
-        let piece_points = piece.get_piece_points().unwrap();
-        for point in piece_points.iter() {
-            if point.0 < 0 || point.1 < 0 {
-                return Err(MinoesError::OutOfBounds(OutOfBoundsError));
+    // Apply a single event to the game. Time only moves forward through `Event::TimePassed`, one
+    // frame at a time, so the same events always play out the same game.
+    pub fn handle_event(&mut self, event: Event) {
+        // Once the game is over there is nothing left to pause
+        if let Event::TogglePause = event {
+            if self.game_over.is_none() {
+                self.paused = !self.paused;
             }
-            if point.0 >= self.playfield.len() as i16 || point.1 >= self.playfield[0].len() as i16 {
-                return Err(MinoesError::OutOfBounds(OutOfBoundsError));
+        }
+        // Releases count even when paused, or the button would stay held afterwards
+        if let Event::Release(button) = event {
+            self.release_button(button);
+        }
+        if self.game_over.is_some() || self.paused {
+            return;
+        }
+        let frame_passed = matches!(event, Event::TimePassed);
+        match event {
+            Event::TimePassed => {
+                self.frame += 1;
+                if self.mode == GameMode::Ultra && self.frame >= ULTRA_FRAMES {
+                    self.end_game(GameOverReason::TimeUp);
+                    return;
+                }
+                self.auto_shift();
+                self.advance_phase();
+            }
+            // Pressing buttons between pieces still counts, for the next piece to use
+            Event::Press(button) => self.press_button(button),
+            Event::Repeat(button) => self.repeat_button(button),
+            Event::Release(_) => {}
+            Event::HardDropPiece if self.phase != Phase::Falling => self.hard_drop_buffered = true,
+            // Everything else needs a piece in play
+            _ if self.phase != Phase::Falling => {}
+            Event::MovePiece(TetrisDirection::Down) => {
+                if self.soft_drop_moving_piece().is_ok() {
+                    self.on_piece_moved();
+                }
             }
-            if self.playfield[point.0 as usize][point.1 as usize] == piece.color {
-                continue;
+            Event::MovePiece(direction) => {
+                if self.move_moving_piece(direction).is_ok() {
+                    self.on_piece_moved();
+                }
             }
-
-            if self.playfield[point.0 as usize][point.1 as usize] > 0 {
-                return Err(MinoesError::OverlappingMinoes(OverlappingMinoesError));
+            Event::RotatePiece(rotation) => {
+                if self.rotate_moving_piece(&rotation).is_ok() {
+                    self.on_piece_moved();
+                }
             }
-            self.playfield[point.0 as usize][point.1 as usize] = piece.color;
+            Event::HardDropPiece => {
+                let _ = self.hard_drop_moving_piece();
+            }
+            Event::HoldPiece => {
+                if !self.hold_used {
+                    let _ = self.hold_moving_piece();
+                }
+            }
+            // Quitting is up to whoever drives the game
+            Event::TogglePause | Event::Quit => {}
+        }
+        if self.game_over.is_none() && self.phase == Phase::Falling {
+            self.update_lock_delay(frame_passed);
         }
-        Ok(())
     }
 
-    fn clear_piece_points(&mut self, piece: &Piece) -> Result<(), OutOfBoundsError> {
-        let piece_points = piece.get_piece_points().unwrap();
-        for point in piece_points.iter() {
-            if point.0 < 0 || point.1 < 0 {
-                return Err(OutOfBoundsError);
+    // Let a frame pass in the current phase, moving on to the next phase once its delay is over
+    fn advance_phase(&mut self) {
+        match self.phase {
+            Phase::Falling => self.apply_gravity(),
+            Phase::LineClearDelay(frames_left) if frames_left > 1 => {
+                self.phase = Phase::LineClearDelay(frames_left - 1);
             }
-            if point.0 >= self.playfield.len() as i16 || point.1 >= self.playfield[0].len() as i16 {
-                return Err(OutOfBoundsError);
+            Phase::LineClearDelay(_) => {
+                self.clear_filled_lines();
+                let _ = self.start_entry_delay();
+            }
+            Phase::EntryDelay(frames_left) if frames_left > 1 => {
+                self.phase = Phase::EntryDelay(frames_left - 1);
             }
-            if self.playfield[point.0 as usize][point.1 as usize] != piece.color {
-                continue;
+            Phase::EntryDelay(_) => {
+                let _ = self.spawn_next_piece();
             }
-            self.playfield[point.0 as usize][point.1 as usize] = 0;
         }
-        Ok(())
     }
 
-    // TODO: remove pub
-    pub fn piece_is_in_allowed_position(&self, piece: &Piece) -> Result<(), MinoesError> {
-        let piece_points = match piece.get_piece_points() {
-            Ok(points) => points,
-            Err(_) => return Err(MinoesError::OutOfBounds(OutOfBoundsError)),
-        };
-
-        for point in piece_points.iter() {
-            if point.0 < 0 || point.1 < 0 {
-                return Err(MinoesError::OutOfBounds(OutOfBoundsError));
-            }
-            if point.0 >= self.playfield.len() as i16 || point.1 >= self.playfield[0].len() as i16 {
-                return Err(MinoesError::OutOfBounds(OutOfBoundsError));
-            }
-            if self.playfield[point.0 as usize][point.1 as usize] > 0 {
-                return Err(MinoesError::OverlappingMinoes(OverlappingMinoesError));
+    fn start_entry_delay(&mut self) -> Result<(), GameOverReason> {
+        if self.mode == GameMode::Endless && self.stack_is_above_skyline() {
+            // Nothing tops out in endless mode, the stack vanishes before it can
+            for row in self.playfield.iter_mut() {
+                row.fill(Cell::Empty);
             }
         }
-        return Ok(());
+        if self.mode == GameMode::Dig {
+            self.add_garbage()?;
+        }
+        if self.entry_delay > 0 {
+            self.phase = Phase::EntryDelay(self.entry_delay);
+            Ok(())
+        } else {
+            self.spawn_next_piece()
+        }
     }
 
-    // TODO: make private
-    pub fn piece_is_out_of_bounds(&self, piece: &Piece) -> bool {
-        let piece_points = match piece.get_piece_points() {
-            Ok(points) => points,
-            Err(_) => return true,
-        };
+    fn spawn_next_piece(&mut self) -> Result<(), GameOverReason> {
+        self.phase = Phase::Falling;
+        let next_piece = self.get_next_piece_in_queue(true);
+        self.hold_used = false;
+        if self.add_piece_to_field(next_piece).is_err() {
+            return Err(self.end_game(GameOverReason::BlockOut));
+        }
+        if self.hard_drop_buffered {
+            self.hard_drop_buffered = false;
+            return self.hard_drop_moving_piece();
+        }
+        Ok(())
+    }
 
-        for point in piece_points.iter() {
-            if point.0 < 0 || point.1 < 0 {
-                return true;
+    // The gravity of the current level, in cells per frame
+    pub fn gravity(&self) -> f64 {
+        let index = (self.level as usize).saturating_sub(1);
+        self.gravity_table[index.min(self.gravity_table.len() - 1)]
+    }
+
+    fn press_button(&mut self, button: Button) {
+        self.keep_button_down(button);
+        if matches!(button, Button::Left | Button::Right) {
+            self.last_shift_press = Some((button, self.frame));
+        }
+        match button {
+            Button::Left => self.shift_moving_piece(LEFT, 1),
+            Button::Right => self.shift_moving_piece(RIGHT, 1),
+            Button::SoftDrop => {
+                if self.phase == Phase::Falling && self.soft_drop_moving_piece().is_ok() {
+                    self.on_piece_moved();
+                }
             }
-            if point.0 >= self.playfield.len() as i16 || point.1 >= self.playfield[0].len() as i16 {
-                return true;
+            Button::Rotate(rotation) => {
+                if self.phase == Phase::Falling && self.rotate_moving_piece(&rotation).is_ok() {
+                    self.on_piece_moved();
+                }
+            }
+            Button::Hold => {
+                if self.phase == Phase::Falling && !self.hold_used {
+                    let _ = self.hold_moving_piece();
+                }
             }
         }
-        return false;
     }
 
-    pub fn piece_is_overlapping_with(&self, piece: &Piece, checked: i16) -> bool {
-        let piece_points = piece.get_piece_points().unwrap();
+    // Mark a button as held, for DAS, soft drop, IRS and IHS to pick up, without acting on it
+    fn keep_button_down(&mut self, button: Button) {
+        match button {
+            Button::Left => self.left_held = true,
+            Button::Right => self.right_held = true,
+            Button::SoftDrop => self.soft_drop_held = true,
+            Button::Rotate(rotation) => self.rotation_held = Some(rotation),
+            Button::Hold => self.hold_held = true,
+        }
+        if matches!(button, Button::Left | Button::Right) && self.shift_button != Some(button) {
+            self.shift_button = Some(button);
+            self.das_charge = 0;
+            self.arr_timer = 0;
+        }
+    }
 
-        for point in piece_points.iter() {
-            if self.playfield[point.0 as usize][point.1 as usize] == checked {
-                return true;
+    // Hold down a tapped button that turned out to be held all along. A direction was charging DAS
+    // since it was pressed, the front-end just couldn't know it yet.
+    fn repeat_button(&mut self, button: Button) {
+        self.keep_button_down(button);
+        if let Some((pressed, frame)) = self.last_shift_press {
+            if pressed == button && self.shift_button == Some(button) {
+                let held_frames =
+                    (self.frame - frame).min(self.das.saturating_sub(1) as u64) as u32;
+                self.das_charge = self.das_charge.max(held_frames);
             }
         }
-        return false;
     }
 
-    fn fill_field_with_dropped_points(&mut self, points: [Point; 4]) {
-        for point in points.iter() {
-            self.playfield[point.0 as usize][point.1 as usize] = 8;
+    fn release_button(&mut self, button: Button) {
+        match button {
+            Button::Left => self.left_held = false,
+            Button::Right => self.right_held = false,
+            Button::SoftDrop => self.soft_drop_held = false,
+            Button::Rotate(rotation) => {
+                if self.rotation_held == Some(rotation) {
+                    self.rotation_held = None;
+                }
+            }
+            Button::Hold => self.hold_held = false,
+        }
+        if self.shift_button == Some(button) {
+            // Go back to the other direction if it is still held, charging it from scratch
+            self.shift_button = match button {
+                Button::Left if self.right_held => Some(Button::Right),
+                Button::Right if self.left_held => Some(Button::Left),
+                _ => None,
+            };
+            self.das_charge = 0;
+            self.arr_timer = 0;
         }
     }
 
-    fn update_ghost_piece(&mut self) {
-        if let Some(ghost_piece) = &self.ghost_piece {
-            self.clear_piece_points(&ghost_piece.clone()).unwrap();
+    // Charge DAS while a direction is held, and repeat the move once it is charged. The charge
+    // carries over from one piece to the next.
+    fn auto_shift(&mut self) {
+        let direction = match self.shift_button {
+            Some(Button::Left) => LEFT,
+            Some(Button::Right) => RIGHT,
+            _ => return,
+        };
+        if self.das_charge < self.das {
+            self.das_charge += 1;
+            if self.das_charge < self.das {
+                return;
+            }
+            self.arr_timer = 0;
+        } else {
+            self.arr_timer += 1;
+            if self.arr_timer < self.arr {
+                return;
+            }
+            self.arr_timer = 0;
         }
-        let mut ghost_piece = self.moving_piece.clone();
-        ghost_piece.color = -1;
+        let cells = if self.arr == 0 { self.width } else { 1 };
+        self.shift_moving_piece(direction, cells);
+    }
 
-        let mut lowered_counter = 0;
-        while !self.piece_is_out_of_bounds(&ghost_piece)
-            && !self.piece_is_overlapping_with(&ghost_piece, 8)
-        {
-            ghost_piece.move_piece(&TetrisDirection::Down);
-            lowered_counter += 1;
+    // Move the piece sideways by up to `cells` cells, stopping at the first obstacle
+    fn shift_moving_piece(&mut self, direction: TetrisDirection, cells: usize) {
+        if self.phase != Phase::Falling {
+            return;
         }
-
-        // Move the ghost piece up one step, since we lowered it one step too far
-        if lowered_counter != 0 {
-            ghost_piece.move_piece(&TetrisDirection::Up);
+        let mut moved = false;
+        for _ in 0..cells {
+            if self.move_moving_piece(direction).is_err() {
+                break;
+            }
+            moved = true;
         }
-        match self.fill_piece_points(&ghost_piece) {
-            Ok(_) => {}
-            Err(_) => {}
+        if moved {
+            self.on_piece_moved();
         }
-        self.ghost_piece = Some(ghost_piece);
-        // Re-draw the moving piece, since we might have overwritten it
-        // While drawing the ghost piece, and then gave up
-        self.fill_piece_points(&self.moving_piece.clone()).unwrap();
-    }
-
-    pub fn add_piece_to_field(&mut self, piece: Piece) -> Result<(), MinoesError> {
-        self.moving_piece = piece;
-        self.fill_piece_points(&self.moving_piece.clone())?;
-        self.update_ghost_piece();
-        return Ok(());
     }
 
-    pub fn rotate_moving_piece(&mut self, direction: &Rotation) -> Result<(), OutOfBoundsError> {
-        let mut ok = false;
-        let old_piece = self.moving_piece.clone();
-        self.clear_piece_points(&old_piece).unwrap();
-        for (kick_x, kick_y) in KICKS.iter() {
-            self.moving_piece.move_piece_by(*kick_x, *kick_y);
-            self.moving_piece.rotate_piece(direction);
-            if let Ok(_) = self.piece_is_in_allowed_position(&self.moving_piece.clone()) {
-                ok = true;
+    // Move the piece down by the cells gravity pulled it during one frame, with soft dropping
+    // making gravity `soft_drop_factor` times stronger
+    fn apply_gravity(&mut self) {
+        let mut gravity = self.gravity();
+        if self.soft_drop_held {
+            gravity *= self.soft_drop_factor as f64;
+        }
+        let cells = if gravity >= INSTANT_GRAVITY {
+            self.playfield.len()
+        } else {
+            self.gravity_progress += gravity;
+            let cells = self.gravity_progress.floor();
+            self.gravity_progress -= cells;
+            cells as usize
+        };
+        let mut moved = false;
+        for _ in 0..cells {
+            if self.move_moving_piece(DOWN).is_err() {
+                // Gravity doesn't build up while the piece rests on the stack
+                self.gravity_progress = 0.0;
                 break;
             }
-            self.moving_piece.move_piece_by(-*kick_x, -*kick_y);
-            self.moving_piece.rotate_piece(&direction.other_direction());
+            if self.soft_drop_held {
+                self.score = self.score.saturating_add(SOFT_DROP_POINTS_PER_CELL);
+            }
+            moved = true;
         }
-        if !ok {
-            self.fill_piece_points(&old_piece).unwrap();
-            return Err(OutOfBoundsError);
+        if moved {
+            self.on_piece_moved();
         }
-        self.fill_piece_points(&self.moving_piece.clone()).unwrap();
-        self.update_ghost_piece();
-        return Ok(());
     }
 
-    pub fn move_moving_piece(
-        &mut self,
-        direction: TetrisDirection,
+    // How long until the moving piece locks, in frames, if it is resting on the stack
+    pub fn lock_delay_remaining(&self) -> Option<u32> {
+        self.lock_delay_elapsed
+            .map(|elapsed| self.lock_delay.saturating_sub(elapsed))
+    }
+
+    fn piece_is_grounded(&self) -> bool {
+        let mut lowered_piece = self.moving_piece;
+        lowered_piece.move_piece(&DOWN);
+        self.piece_is_in_allowed_position(&lowered_piece).is_err()
+    }
+
+    fn piece_lowest_row(piece: &Piece) -> i16 {
+        let points = piece.get_piece_points().unwrap();
+        points.iter().map(|point| point.0).max().unwrap()
+    }
+
+    // Restart the lock delay if the policy allows it, after the moving piece moved or rotated
+    fn on_piece_moved(&mut self) {
+        let lowest_row = Game::piece_lowest_row(&self.moving_piece);
+        let descended = lowest_row > self.lowest_row;
+        self.lowest_row = self.lowest_row.max(lowest_row);
+        match self.lock_delay_policy {
+            LockDelayPolicy::MoveReset => {
+                if descended {
+                    self.lock_resets = 0;
+                    self.lock_delay_elapsed = None;
+                } else if self.lock_delay_elapsed.is_some() && self.lock_resets < MOVE_RESET_LIMIT {
+                    self.lock_resets += 1;
+                    self.lock_delay_elapsed = None;
+                }
+            }
+            LockDelayPolicy::StepReset => {
+                if descended {
+                    self.lock_delay_elapsed = None;
+                }
+            }
+            LockDelayPolicy::NoReset => {}
+        }
+    }
+
+    // Start the lock delay once the moving piece touches down, and lock it when the delay is over.
+    // The delay is paused while the piece is off the ground, only `on_piece_moved` restarts it.
+    fn update_lock_delay(&mut self, frame_passed: bool) {
+        if !self.piece_is_grounded() {
+            return;
+        }
+        if self.lock_delay_policy == LockDelayPolicy::MoveReset
+            && self.lock_resets >= MOVE_RESET_LIMIT
+        {
+            // Out of resets, the piece locks as soon as it touches down
+            let _ = self.hard_drop_moving_piece();
+            return;
+        }
+        let elapsed = self.lock_delay_elapsed.get_or_insert(0);
+        if frame_passed {
+            *elapsed += 1;
+        }
+        if *elapsed >= self.lock_delay {
+            let _ = self.hard_drop_moving_piece();
+        }
+    }
+
+    pub fn get_next_piece_in_queue(&mut self, pop: bool) -> Piece {
+        if pop {
+            Piece::spawn(self.next_pieces.pop(), self.width)
+        } else {
+            Piece::spawn(self.next_pieces.peek(), self.width)
+        }
+    }
+
+    // The upcoming pieces shown in the preview, the next one first
+    pub fn upcoming_pieces(&self) -> Vec<PieceType> {
+        self.next_pieces.upcoming(self.preview).collect()
+    }
+
+    // TODO: remove pub
+    pub fn piece_is_in_allowed_position(&self, piece: &Piece) -> Result<(), MinoesError> {
+        if self.piece_is_out_of_bounds(piece) {
+            return Err(MinoesError::OutOfBounds(OutOfBoundsError));
+        }
+        if self.piece_is_overlapping(piece) {
+            return Err(MinoesError::OverlappingMinoes(OverlappingMinoesError));
+        }
+        Ok(())
+    }
+
+    // TODO: make private
+    pub fn piece_is_out_of_bounds(&self, piece: &Piece) -> bool {
+        let piece_points = match piece.get_piece_points() {
+            Ok(points) => points,
+            Err(_) => return true,
+        };
+
+        for point in piece_points.iter() {
+            if point.0 < 0 || point.1 < 0 {
+                return true;
+            }
+            if point.0 >= self.playfield.len() as i16 || point.1 >= self.playfield[0].len() as i16 {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Whether the piece covers any locked mino. The piece must be in bounds
+    pub fn piece_is_overlapping(&self, piece: &Piece) -> bool {
+        let piece_points = piece.get_piece_points().unwrap();
+
+        for point in piece_points.iter() {
+            if !self.playfield[point.0 as usize][point.1 as usize].is_empty() {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn fill_field_with_dropped_piece(&mut self, piece: &Piece) {
+        for point in piece.get_piece_points().unwrap().iter() {
+            self.playfield[point.0 as usize][point.1 as usize] = Cell::Locked(piece.piece_type);
+        }
+    }
+
+    fn update_ghost_piece(&mut self) {
+        let mut ghost_piece = self.moving_piece;
+        ghost_piece.move_piece(&TetrisDirection::Down);
+        while self.piece_is_in_allowed_position(&ghost_piece).is_ok() {
+            ghost_piece.move_piece(&TetrisDirection::Down);
+        }
+        // Move the ghost piece up one step, since we lowered it one step too far
+        ghost_piece.move_piece(&TetrisDirection::Up);
+        self.ghost_piece = Some(ghost_piece);
+    }
+
+    // The playfield as it should be shown, with the ghost and moving pieces drawn over the
+    // locked minoes
+    pub fn rendered_playfield(&self) -> Vec<Vec<Cell>> {
+        let mut playfield = self.playfield.clone();
+        if let Some(ghost_piece) = &self.ghost_piece {
+            for point in ghost_piece.get_piece_points().unwrap().iter() {
+                playfield[point.0 as usize][point.1 as usize] = Cell::Ghost(ghost_piece.piece_type);
+            }
+        }
+        match self.phase {
+            Phase::Falling => {
+                if let Ok(points) = self.moving_piece.get_piece_points() {
+                    for point in points.iter() {
+                        playfield[point.0 as usize][point.1 as usize] =
+                            Cell::Active(self.moving_piece.piece_type);
+                    }
+                }
+            }
+            Phase::LineClearDelay(_) => {
+                for row in playfield.iter_mut() {
+                    if row.iter().all(|cell| !cell.is_empty()) {
+                        row.fill(Cell::Clearing);
+                    }
+                }
+            }
+            Phase::EntryDelay(_) => {}
+        }
+        playfield
+    }
+
+    pub fn add_piece_to_field(&mut self, mut piece: Piece) -> Result<(), MinoesError> {
+        // Initial Hold System: holding the hold button swaps the piece before it even appears
+        if self.hold_held && !self.hold_used {
+            let held_piece = self.held_piece.replace(piece);
+            piece = match held_piece {
+                Some(held_piece) => Piece::spawn(held_piece.piece_type, self.width),
+                None => self.get_next_piece_in_queue(true),
+            };
+            self.hold_used = true;
+        }
+        // Initial Rotation System: holding a rotation button spawns the piece rotated, kicking it
+        // if needed, or unrotated if no kick fits
+        if let Some(rotation) = self.rotation_held {
+            if let Some((rotated_piece, _)) = self.kicked_rotation(&piece, &rotation) {
+                piece = rotated_piece;
+            }
+        }
+        self.piece_is_in_allowed_position(&piece)?;
+        self.moving_piece = piece;
+        // Pieces drop one row right after spawning, if nothing is in the way
+        let _ = self.move_moving_piece(DOWN);
+        self.update_ghost_piece();
+        self.lock_delay_elapsed = None;
+        self.lock_resets = 0;
+        self.lowest_row = Game::piece_lowest_row(&self.moving_piece);
+        self.gravity_progress = 0.0;
+        Ok(())
+    }
+
+    // Rotate `piece` with the first kick that fits, returning it along with the index of the kick
+    fn kicked_rotation(&self, piece: &Piece, direction: &Rotation) -> Option<(Piece, usize)> {
+        let mut rotated_piece = *piece;
+        rotated_piece.rotate_piece(direction);
+        for (kick_index, (kick_x, kick_y)) in piece.kicks(direction).iter().enumerate() {
+            let mut kicked_piece = rotated_piece;
+            // Kicks are (x, y) with y pointing up, while points are (row, column)
+            kicked_piece.move_piece_by(-*kick_y, *kick_x);
+            if self.piece_is_in_allowed_position(&kicked_piece).is_ok() {
+                return Some((kicked_piece, kick_index));
+            }
+        }
+        None
+    }
+
+    // Whether there is a moving piece to act on, which there isn't between pieces or once the game
+    // is over
+    fn piece_in_play(&self) -> bool {
+        self.phase == Phase::Falling && self.game_over.is_none()
+    }
+
+    pub fn rotate_moving_piece(&mut self, direction: &Rotation) -> Result<(), OutOfBoundsError> {
+        if !self.piece_in_play() {
+            return Err(OutOfBoundsError);
+        }
+        let (kicked_piece, kick_index) = self
+            .kicked_rotation(&self.moving_piece, direction)
+            .ok_or(OutOfBoundsError)?;
+        self.moving_piece = kicked_piece;
+        self.last_rotation_kick = Some((*direction, kick_index));
+        self.update_ghost_piece();
+        Ok(())
+    }
+
+    pub fn move_moving_piece(
+        &mut self,
+        direction: TetrisDirection,
         // TODO: this should return either OutOfBoundsError or OverlappingMinoesError
     ) -> Result<(), OutOfBoundsError> {
-        self.clear_piece_points(&self.moving_piece.clone()).unwrap();
-        self.moving_piece.move_piece(&direction);
-        if let Err(_) = self.piece_is_in_allowed_position(&self.moving_piece.clone()) {
-            self.moving_piece
-                .move_piece(&direction.opposite_direction());
-            self.fill_piece_points(&self.moving_piece.clone()).unwrap();
+        if !self.piece_in_play() {
+            return Err(OutOfBoundsError);
+        }
+        let mut moved_piece = self.moving_piece;
+        moved_piece.move_piece(&direction);
+        if self.piece_is_in_allowed_position(&moved_piece).is_err() {
             return Err(OutOfBoundsError);
         }
+        self.moving_piece = moved_piece;
+        self.last_rotation_kick = None;
         self.update_ghost_piece();
-        self.fill_piece_points(&self.moving_piece.clone()).unwrap();
-        return Ok(());
+        Ok(())
+    }
+
+    // Like moving the piece down, but scores a point for the cell it dropped
+    pub fn soft_drop_moving_piece(&mut self) -> Result<(), OutOfBoundsError> {
+        self.move_moving_piece(DOWN)?;
+        self.score = self.score.saturating_add(SOFT_DROP_POINTS_PER_CELL);
+        Ok(())
     }
 
     // There is no need for a separate lock function, since a lock is really a hard drop from
     // lowest possible height
-    pub fn hard_drop_moving_piece(&mut self) -> Result<(), MinoesError> {
-        self.clear_piece_points(&self.moving_piece.clone())?;
-        self.clear_piece_points(&self.ghost_piece.unwrap().clone())?;
-        self.moving_piece = self.ghost_piece.unwrap().clone();
+    pub fn hard_drop_moving_piece(&mut self) -> Result<(), GameOverReason> {
+        // The last piece already locked, and the next one isn't in play yet
+        let ghost_piece = match self.ghost_piece {
+            Some(ghost_piece) if self.piece_in_play() => ghost_piece,
+            _ => return Ok(()),
+        };
+        let dropped_cells = ghost_piece.center.0 - self.moving_piece.center.0;
+        self.score = self
+            .score
+            .saturating_add(HARD_DROP_POINTS_PER_CELL * dropped_cells as u64);
+        if dropped_cells > 0 {
+            self.last_rotation_kick = None;
+        }
+        self.moving_piece = ghost_piece;
         self.ghost_piece = None;
-        self.fill_field_with_dropped_points(self.moving_piece.get_piece_points().unwrap());
-        let cleared_lines_count = self.clear_filled_lines();
-        self.adjust_level(cleared_lines_count);
-        self.adjust_score(cleared_lines_count);
-        let next_piece = self.get_next_piece_in_queue(true);
-        self.add_piece_to_field(next_piece)?;
-        self.hold_used = false;
-        return Ok(());
+        self.lock_delay_elapsed = None;
+        let dropped_piece = self.moving_piece;
+        // T-spins depend on the stack around the piece before it locks and lines are cleared
+        let t_spin = self.detect_t_spin();
+        self.fill_field_with_dropped_piece(&dropped_piece);
+        if let Some(reason) = self.lock_out_reason(&dropped_piece) {
+            if self.mode != GameMode::Endless {
+                return Err(self.end_game(reason));
+            }
+        }
+        let filled_rows = self
+            .playfield
+            .iter()
+            .filter(|row| row.iter().all(|cell| !cell.is_empty()));
+        let lines = filled_rows.clone().count();
+        let garbage_lines = filled_rows
+            .filter(|row| row.contains(&Cell::Garbage))
+            .count();
+        self.garbage_cleared = self.garbage_cleared.saturating_add(garbage_lines as u32);
+        let line_clear = LineClear {
+            lines,
+            t_spin,
+            // Nothing is left once the filled rows are cleared
+            perfect_clear: lines > 0
+                && self.playfield.iter().all(|row| {
+                    row.iter().all(|cell| cell.is_empty())
+                        || row.iter().all(|cell| !cell.is_empty())
+                }),
+        };
+        // Before scoring, which moves the back-to-back chain on
+        self.adjust_level(&line_clear);
+        self.adjust_score(&line_clear);
+        self.last_clear = if line_clear.lines > 0 || line_clear.t_spin.is_some() {
+            Some(line_clear)
+        } else {
+            None
+        };
+        let goal_reached = match self.mode {
+            GameMode::Marathon => self.lines_cleared >= MARATHON_LINES,
+            GameMode::Sprint => self.lines_cleared >= SPRINT_LINES,
+            GameMode::Dig => self.garbage_cleared >= self.dig_lines,
+            GameMode::Ultra | GameMode::Endless => false,
+        };
+        if goal_reached {
+            return Err(self.end_game(GameOverReason::GoalReached));
+        }
+        if lines > 0 && self.line_clear_delay > 0 {
+            self.phase = Phase::LineClearDelay(self.line_clear_delay);
+            return Ok(());
+        }
+        self.clear_filled_lines();
+        self.start_entry_delay()
+    }
+
+    pub fn hold_moving_piece(&mut self) -> Result<(), GameOverReason> {
+        if !self.piece_in_play() {
+            return Ok(());
+        }
+        let next_piece = match self.held_piece {
+            Some(piece) => Piece::spawn(piece.piece_type, self.width),
+            None => self.get_next_piece_in_queue(true),
+        };
+        let previous_piece = self.moving_piece;
+        // Set first, so the piece coming out isn't swapped right back by the Initial Hold System
+        self.hold_used = true;
+        if self.add_piece_to_field(next_piece).is_err() {
+            return Err(self.end_game(GameOverReason::BlockOut));
+        }
+        self.held_piece = Some(previous_piece);
+        Ok(())
+    }
+
+    fn lock_out_reason(&self, locked_piece: &Piece) -> Option<GameOverReason> {
+        let points = locked_piece.get_piece_points().unwrap();
+        let skyline = (self.playfield.len() - self.visible_height) as i16;
+        let above_skyline = points.iter().filter(|point| point.0 < skyline).count();
+        if above_skyline == points.len() {
+            Some(GameOverReason::LockOut)
+        } else if above_skyline > 0 && self.partial_lock_out {
+            Some(GameOverReason::PartialLockOut)
+        } else {
+            None
+        }
     }
 
-    pub fn hold_moving_piece(&mut self) -> Result<(), MinoesError> {
-        self.clear_piece_points(&self.moving_piece.clone())?;
-        self.clear_piece_points(&self.ghost_piece.unwrap().clone())?;
-        match self.held_piece {
-            Some(piece) => {
-                let old_held_piece = piece;
-                self.held_piece = Some(self.moving_piece.clone());
-                self.moving_piece = Piece::new(old_held_piece.piece_type);
+    // Push garbage rows in from the bottom until there are `garbage_height` of them, or until all
+    // of dig mode's garbage has been added
+    fn add_garbage(&mut self) -> Result<(), GameOverReason> {
+        let mut garbage_rows = self
+            .playfield
+            .iter()
+            .filter(|row| row.contains(&Cell::Garbage))
+            .count();
+        while garbage_rows < self.garbage_height() && self.garbage_added < self.dig_lines {
+            if self.playfield[0].iter().any(|cell| !cell.is_empty()) {
+                return Err(self.end_game(GameOverReason::TopOut));
             }
-            None => {
-                self.held_piece = Some(self.moving_piece.clone());
-                self.moving_piece = self.get_next_piece_in_queue(true);
+            self.playfield.remove(0);
+            self.playfield.push(self.garbage_generator.next_row());
+            self.garbage_added += 1;
+            garbage_rows += 1;
+        }
+        Ok(())
+    }
+
+    // Garbage stays well clear of the spawn rows, however short the playfield is
+    fn garbage_height(&self) -> usize {
+        GARBAGE_HEIGHT.min(self.visible_height / 2)
+    }
+
+    fn stack_is_above_skyline(&self) -> bool {
+        let skyline = self.playfield.len() - self.visible_height;
+        self.playfield[..skyline]
+            .iter()
+            .any(|row| row.iter().any(|cell| !cell.is_empty()))
+    }
+
+    fn end_game(&mut self, reason: GameOverReason) -> GameOverReason {
+        self.game_over = Some(reason);
+        reason
+    }
+
+    // The lines the current level needs in total before moving on to the next one
+    pub fn level_goal_total(&self) -> u32 {
+        match self.level_goal {
+            LevelGoal::Variable => self.level.saturating_mul(VARIABLE_GOAL_LINES_PER_LEVEL),
+            LevelGoal::Fixed => FIXED_GOAL_LINES,
+        }
+    }
+
+    // The lines still missing to reach the next level
+    pub fn lines_to_next_level(&self) -> u32 {
+        self.level_goal_total().saturating_sub(self.goal_lines)
+    }
+
+    // The lines still missing to reach the next level, or to finish a marathon, a sprint or a dig
+    pub fn lines_to_goal(&self) -> u32 {
+        match self.mode {
+            GameMode::Marathon => self
+                .lines_to_next_level()
+                .min(MARATHON_LINES.saturating_sub(self.lines_cleared)),
+            GameMode::Sprint => SPRINT_LINES.saturating_sub(self.lines_cleared),
+            GameMode::Dig => self.dig_lines.saturating_sub(self.garbage_cleared),
+            _ => self.lines_to_next_level(),
+        }
+    }
+
+    // The time shown while playing, which counts down in ultra
+    pub fn clock(&self) -> u64 {
+        match self.mode {
+            GameMode::Ultra => ULTRA_FRAMES.saturating_sub(self.frame),
+            _ => self.frame,
+        }
+    }
+
+    fn adjust_level(&mut self, line_clear: &LineClear) {
+        self.lines_cleared = self.lines_cleared.saturating_add(line_clear.lines as u32);
+        let goal_lines = match self.level_goal {
+            LevelGoal::Variable if line_clear.is_difficult() && self.back_to_back.is_some() => {
+                line_clear.goal_lines() * 3 / 2
             }
+            LevelGoal::Variable => line_clear.goal_lines(),
+            LevelGoal::Fixed => line_clear.lines as u32,
+        };
+        self.goal_lines = self.goal_lines.saturating_add(goal_lines);
+        let goal = self.level_goal_total();
+        let can_level_up = match self.mode {
+            GameMode::Marathon => self.level < MARATHON_LEVELS,
+            GameMode::Endless => true,
+            GameMode::Sprint | GameMode::Ultra | GameMode::Dig => false,
+        };
+        if can_level_up && self.goal_lines >= goal {
+            // Lines past the goal count towards the next one
+            self.goal_lines -= goal;
+            self.level = self.level.saturating_add(1);
         }
-        self.fill_piece_points(&self.moving_piece.clone()).unwrap();
-        self.add_piece_to_field(self.moving_piece)?;
-        self.hold_used = true;
-        return Ok(());
     }
 
-    fn adjust_level(&mut self, cleared_lines: usize) {
-        match cleared_lines {
-            1 => self.lines_cleared += 1,
-            2 => self.lines_cleared += 3,
-            3 => self.lines_cleared += 5,
-            4 => self.lines_cleared += 8,
-            _ => {}
+    fn adjust_score(&mut self, line_clear: &LineClear) {
+        let level = self.level as u64;
+        let mut points = line_clear.base_score().saturating_mul(level);
+        if line_clear.lines == 0 {
+            self.combo = None;
+            self.score = self.score.saturating_add(points);
+            return;
         }
-        if self.lines_cleared >= self.level * 5 {
-            self.level += 1;
-            self.lines_cleared = 0;
-            let new_speed: u64 = ((0.8 - ((self.level - 1) as f64 * 0.007)).powi(self.level as i32)
-                * 1000.0)
-                .round() as u64;
-            self.speed_info_sender.send(new_speed).unwrap();
+        if line_clear.is_difficult() {
+            let back_to_back = self.back_to_back.map_or(0, |chain| chain.saturating_add(1));
+            if back_to_back > 0 {
+                points = points.saturating_mul(3) / 2;
+            }
+            self.back_to_back = Some(back_to_back);
+        } else {
+            self.back_to_back = None;
+        }
+        if line_clear.perfect_clear {
+            let back_to_back = self.back_to_back.is_some_and(|chain| chain > 0);
+            let bonus = line_clear
+                .perfect_clear_bonus(back_to_back)
+                .saturating_mul(level);
+            points = points.saturating_add(bonus);
+            self.perfect_clears = self.perfect_clears.saturating_add(1);
         }
+        let combo = self.combo.map_or(0, |combo| combo.saturating_add(1));
+        let combo_bonus = COMBO_POINTS
+            .saturating_mul(combo as u64)
+            .saturating_mul(level);
+        points = points.saturating_add(combo_bonus);
+        self.combo = Some(combo);
+        self.score = self.score.saturating_add(points);
     }
 
-    fn adjust_score(&mut self, cleared_lines: usize) {
-        match cleared_lines {
-            1 => self.score += 100 * self.level,
-            2 => self.score += 300 * self.level,
-            3 => self.score += 500 * self.level,
-            4 => self.score += 800 * self.level,
-            _ => {}
+    // Uses the 3-corner rule: a T piece that got in place by rotating is a T-spin when at least 3
+    // of the corners diagonal to its center are blocked. Unless both corners it points towards are
+    // blocked, it's only a mini T-spin.
+    fn detect_t_spin(&self) -> Option<TSpin> {
+        if self.moving_piece.piece_type != PieceType::T {
+            return None;
+        }
+        let (rotation, kick_index) = self.last_rotation_kick?;
+        let Point(row, column) = self.moving_piece.center;
+        let is_blocked = |(row_offset, column_offset): (i16, i16)| {
+            let (row, column) = (row + row_offset, column + column_offset);
+            row < 0
+                || column < 0
+                || row >= self.playfield.len() as i16
+                || column >= self.width as i16
+                || !self.playfield[row as usize][column as usize].is_empty()
+        };
+        let (front_corners, back_corners) = match self.moving_piece.current_rotation_id {
+            0 => ([(-1, -1), (-1, 1)], [(1, -1), (1, 1)]),
+            1 => ([(-1, 1), (1, 1)], [(-1, -1), (1, -1)]),
+            2 => ([(1, -1), (1, 1)], [(-1, -1), (-1, 1)]),
+            _ => ([(-1, -1), (1, -1)], [(-1, 1), (1, 1)]),
+        };
+        let blocked_front = front_corners.into_iter().filter(|c| is_blocked(*c)).count();
+        let blocked_back = back_corners.into_iter().filter(|c| is_blocked(*c)).count();
+        if blocked_front + blocked_back < 3 {
+            None
+        } else if blocked_front == 2
+            || (rotation != Rotation::HalfTurn && kick_index == T_SPIN_UPGRADE_KICK)
+        {
+            Some(TSpin::Full)
+        } else {
+            Some(TSpin::Mini)
         }
     }
 
-    #[allow(unused_assignments)]
     pub fn clear_filled_lines(&mut self) -> usize {
-        let mut cleared_lines: usize = 0;
-        for last_line in (0..self.playfield.len()).rev() {
-            while self.playfield[last_line].iter().all(|x| *x == 8) {
-                cleared_lines += 1;
-                for i in (1..=last_line).rev() {
-                    self.playfield[i] = self.playfield[i - 1];
-                }
-            }
+        let height = self.playfield.len();
+        self.playfield
+            .retain(|row| row.iter().any(|cell| cell.is_empty()));
+        let cleared_lines = height - self.playfield.len();
+        for _ in 0..cleared_lines {
+            self.playfield.insert(0, vec![Cell::Empty; self.width]);
+        }
+        cleared_lines
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct DrawOptions {
+    // Draw every locked mino in the same gray instead of the color of the piece it came from
+    pub gray_stack: bool,
+    // How many rows of the hidden buffer zone above the skyline to show
+    pub buffer_peek: usize,
+}
+
+// The colors from the guideline
+fn piece_color(piece_type: PieceType) -> Color {
+    match piece_type {
+        PieceType::I => Color::Cyan,
+        PieceType::J => Color::Blue,
+        // Orange, which has no named terminal color
+        PieceType::L => Color::Indexed(208),
+        PieceType::O => Color::Yellow,
+        PieceType::S => Color::Green,
+        PieceType::T => Color::Magenta,
+        PieceType::Z => Color::Red,
+    }
+}
+
+fn cell_color(cell: Cell, options: &DrawOptions) -> Color {
+    match cell {
+        Cell::Empty => Color::Reset,
+        Cell::Active(piece_type) => piece_color(piece_type),
+        Cell::Ghost(_) => Color::White,
+        Cell::Locked(_) if options.gray_stack => Color::DarkGray,
+        Cell::Locked(piece_type) => piece_color(piece_type),
+        Cell::Garbage => Color::Gray,
+        Cell::Clearing => Color::White,
+    }
+}
+
+// Pieces in a preview sit in a box this wide, with a margin of the same width split around it
+const PREVIEW_BOX_WIDTH: usize = 4;
+const PREVIEW_COLUMN_WIDTHS: [Constraint; PREVIEW_BOX_WIDTH * 2] =
+    [Constraint::Length(2); PREVIEW_BOX_WIDTH * 2];
+
+// The rows a preview of `pieces` pieces takes, borders included: every piece takes 2 rows and a
+// blank one, with another blank row at the top
+fn preview_height(pieces: usize) -> u16 {
+    (pieces * 3 + 1 + 2) as u16
+}
+
+// Draw pieces stacked from the top, the way they spawn
+fn piece_preview_table<'a>(
+    pieces: &[PieceType],
+    title: &'a str,
+    options: &DrawOptions,
+) -> Table<'a> {
+    let margin = PREVIEW_BOX_WIDTH / 2;
+    let mut canvas = vec![vec![Cell::Empty; PREVIEW_COLUMN_WIDTHS.len()]; pieces.len() * 3 + 1];
+    for (index, piece_type) in pieces.iter().enumerate() {
+        let piece = Piece::spawn(*piece_type, PREVIEW_BOX_WIDTH);
+        for point in piece.get_piece_points().unwrap() {
+            // Spawned pieces take the row of their center and the one above it
+            let row = index * 3 + 1 + (point.0 - SPAWN_ROW + 1) as usize;
+            canvas[row][point.1 as usize + margin] = Cell::Active(*piece_type);
         }
-        return cleared_lines;
     }
+    let rows =
+        canvas.into_iter().map(|row| {
+            Row::new(row.into_iter().map(|cell| {
+                TableCell::from("").style(Style::default().bg(cell_color(cell, options)))
+            }))
+        });
+    Table::new(rows)
+        .style(Style::default().fg(Color::White))
+        .widths(&PREVIEW_COLUMN_WIDTHS)
+        .column_spacing(0)
+        .block(
+            Block::default()
+                .title(title)
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL),
+        )
 }
 
 pub fn draw_game(
     terminal: &mut Terminal<TermionBackend<RawTerminal<io::Stdout>>>,
     game: &mut Game,
+    options: &DrawOptions,
 ) -> Result<(), Box<dyn error::Error>> {
+    let visible_rows = game.visible_height + options.buffer_peek.min(BUFFER_HEIGHT);
+    let board_height: u16 = (visible_rows + 2).try_into().unwrap();
+    // A short playfield still needs room for the panels beside it
+    let left_height = preview_height(1) + 13 + 3;
+    let height = board_height
+        .max(left_height)
+        .max(preview_height(game.preview));
     terminal.draw(|f| {
         let vertical_chunk = Layout::default()
             .direction(Direction::Vertical)
-            .constraints(
-                [
-                    Constraint::Length((game.playfield.len() + 2).try_into().unwrap()),
-                    Constraint::Min(0),
-                ]
-                .as_ref(),
-            )
+            .constraints([Constraint::Length(height), Constraint::Min(0)].as_ref())
             .split(f.size());
         let chunks = Layout::default()
             .direction(Direction::Horizontal)
             .constraints(
                 [
-                    Constraint::Length(20),
-                    Constraint::Length((game.playfield[0].len() * 2 + 2).try_into().unwrap()),
                     Constraint::Length(21),
+                    Constraint::Length((game.width * 2 + 2).try_into().unwrap()),
+                    Constraint::Length(20),
                     Constraint::Min(0),
                 ]
                 .as_ref(),
             )
             .split(vertical_chunk[0]);
-        let piece_info_section = Layout::default()
+        // Hold, score and lock delay on the left of the playfield, the upcoming pieces on the right
+        let left_section = Layout::default()
             .direction(Direction::Vertical)
             .constraints(
                 [
-                    Constraint::Percentage(45),
-                    Constraint::Percentage(10),
-                    Constraint::Percentage(45),
+                    Constraint::Length(preview_height(1)),
+                    Constraint::Length(13),
+                    Constraint::Length(3),
+                    Constraint::Min(0),
                 ]
                 .as_ref(),
             )
             .split(chunks[0]);
+        let middle_section = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(board_height), Constraint::Min(0)].as_ref())
+            .split(chunks[1]);
+        let right_section = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Length(preview_height(game.preview)),
+                    Constraint::Min(0),
+                ]
+                .as_ref(),
+            )
+            .split(chunks[2]);
 
-        let mut next_piece_field = game.playfield.clone().map(|row| row.map(|_cell| 0));
-        let piece = game.get_next_piece_in_queue(false);
-        let points = piece.get_piece_points().unwrap();
-        for point in points {
-            next_piece_field[(point.0 - piece.center.0 + 3) as usize]
-                [(point.1 - piece.center.1 + 4) as usize] = piece.color;
-        }
-        let next_piece_rows = next_piece_field.map(|row| {
-            Row::new(row.map(|el| {
-                let color = match el {
-                    // Pieces
-                    n if n.rem_euclid(10) == 1 => Color::Cyan,
-                    n if n.rem_euclid(10) == 2 => Color::Blue,
-                    n if n.rem_euclid(10) == 3 => Color::Red,
-                    n if n.rem_euclid(10) == 4 => Color::Yellow,
-                    n if n.rem_euclid(10) == 5 => Color::Green,
-                    n if n.rem_euclid(10) == 6 => Color::Magenta,
-                    n if n.rem_euclid(10) == 7 => Color::LightRed,
-                    // Pieces already placed
-                    n if n.rem_euclid(10) == 8 => Color::DarkGray,
-                    // Ghost piece
-                    // Empty tile
-                    n if n.rem_euclid(10) == 0 => Color::Reset,
-                    _ => Color::White,
-                };
-                Cell::from("").style(Style::default().bg(color))
-            }))
-        });
-        let next_piece_table = Table::new(next_piece_rows)
-            // You can set the style of the entire Table.
-            .style(Style::default().fg(Color::White))
-            // As any other widget, a Table can be wrapped in a Block.
-            // Columns widths are constrained in the same way as Layout...
-            .widths(&[
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-            ])
-            .column_spacing(0)
-            .highlight_style(Style::default().add_modifier(Modifier::BOLD))
-            .block(
-                Block::default()
-                    .title("Next piece")
-                    .title_alignment(tui::layout::Alignment::Center)
-                    .borders(Borders::ALL),
-            );
-        f.render_widget(next_piece_table, piece_info_section[0]);
-
-        let mut held_piece_field = game.playfield.clone().map(|row| row.map(|_cell| 0));
-        match game.held_piece {
-            Some(piece) => {
-                let points = piece.get_piece_points().unwrap();
-                for point in points {
-                    held_piece_field[(point.0 - piece.center.0 + 3) as usize]
-                        [(point.1 - piece.center.1 + 4) as usize] = piece.color;
-                }
-            }
-            None => {}
-        };
-        let held_piece_rows = held_piece_field.map(|row| {
-            Row::new(row.map(|el| {
-                let color = match el {
-                    // Pieces
-                    n if n.rem_euclid(10) == 1 => Color::Cyan,
-                    n if n.rem_euclid(10) == 2 => Color::Blue,
-                    n if n.rem_euclid(10) == 3 => Color::Red,
-                    n if n.rem_euclid(10) == 4 => Color::Yellow,
-                    n if n.rem_euclid(10) == 5 => Color::Green,
-                    n if n.rem_euclid(10) == 6 => Color::Magenta,
-                    n if n.rem_euclid(10) == 7 => Color::LightRed,
-                    // Pieces already placed
-                    n if n.rem_euclid(10) == 8 => Color::DarkGray,
-                    // Ghost piece
-                    // Empty tile
-                    n if n.rem_euclid(10) == 0 => Color::Reset,
-                    _ => Color::White,
-                };
-                Cell::from("").style(Style::default().bg(color))
-            }))
-        });
-        let held_piece_table = Table::new(held_piece_rows)
-            // You can set the style of the entire Table.
-            .style(Style::default().fg(Color::White))
-            // As any other widget, a Table can be wrapped in a Block.
-            // Columns widths are constrained in the same way as Layout...
-            .widths(&[
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-            ])
-            .column_spacing(0)
-            .highlight_style(Style::default().add_modifier(Modifier::BOLD))
-            .block(
-                Block::default()
-                    .title("Hold")
-                    .title_alignment(tui::layout::Alignment::Center)
-                    .borders(Borders::ALL),
-            );
-        f.render_widget(held_piece_table, piece_info_section[2]);
-
-        let field_rows = game.playfield.map(|row| {
-            Row::new(row.map(|el| {
-                let color = match el {
-                    // Pieces
-                    n if n.rem_euclid(10) == 1 => Color::Cyan,
-                    n if n.rem_euclid(10) == 2 => Color::Blue,
-                    n if n.rem_euclid(10) == 3 => Color::Red,
-                    n if n.rem_euclid(10) == 4 => Color::Yellow,
-                    n if n.rem_euclid(10) == 5 => Color::Green,
-                    n if n.rem_euclid(10) == 6 => Color::Magenta,
-                    n if n.rem_euclid(10) == 7 => Color::LightRed,
-                    // Pieces already placed
-                    n if n.rem_euclid(10) == 8 => Color::DarkGray,
-                    // Ghost piece
-                    n if n < 0 => Color::White,
-                    // Empty tile
-                    n if n.rem_euclid(10) == 0 => Color::Reset,
-                    _ => Color::White,
-                };
-                Cell::from("").style(Style::default().bg(color))
-            }))
-        });
+        let upcoming_pieces = game.upcoming_pieces();
+        let next_piece_table = piece_preview_table(&upcoming_pieces, "Next", options);
+        f.render_widget(next_piece_table, right_section[0]);
+
+        let held_pieces: Vec<PieceType> = game
+            .held_piece
+            .map(|piece| piece.piece_type)
+            .into_iter()
+            .collect();
+        let held_piece_table = piece_preview_table(&held_pieces, "Hold", options);
+        f.render_widget(held_piece_table, left_section[0]);
+
+        let playfield = game.rendered_playfield();
+        let field_rows = playfield[playfield.len() - visible_rows..]
+            .iter()
+            .map(|row| {
+                Row::new(row.iter().map(|cell| {
+                    TableCell::from("").style(Style::default().bg(cell_color(*cell, options)))
+                }))
+            });
+        let column_widths = vec![Constraint::Length(2); game.width];
         let playfield_table = Table::new(field_rows)
             // You can set the style of the entire Table.
             .style(Style::default().fg(Color::White))
             // As any other widget, a Table can be wrapped in a Block.
             // Columns widths are constrained in the same way as Layout...
-            .widths(&[
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-                Constraint::Length(2),
-            ])
+            .widths(&column_widths)
             .column_spacing(0)
             .highlight_style(Style::default().add_modifier(Modifier::BOLD))
             .block(Block::default().borders(Borders::ALL));
-        f.render_widget(playfield_table, chunks[1]);
+        f.render_widget(playfield_table, middle_section[0]);
+        let last_clear = game
+            .last_clear
+            .map(|line_clear| line_clear.to_string())
+            .unwrap_or_default();
+        let perfect_clear = match game.last_clear {
+            Some(line_clear) if line_clear.perfect_clear => "Perfect Clear",
+            _ => "",
+        };
         let text = vec![
-            Spans::from(vec![Span::raw("")]),
-            Spans::from(vec![Span::raw("")]),
+            Spans::from(Span::styled(last_clear, Style::default().fg(Color::Yellow))),
+            Spans::from(Span::styled(
+                perfect_clear,
+                Style::default().fg(Color::Yellow),
+            )),
             Spans::from(vec![Span::raw("Score")]),
             Spans::from(Span::styled(
                 game.score.to_string(),
@@ -877,11 +2188,36 @@ pub fn draw_game(
                 game.level.to_string(),
                 Style::default().fg(Color::Red),
             )),
+            Spans::from(vec![
+                Span::raw("Lines "),
+                Span::styled(
+                    game.lines_cleared.to_string(),
+                    Style::default().fg(Color::Red),
+                ),
+                Span::raw("  Goal "),
+                Span::styled(
+                    game.lines_to_goal().to_string(),
+                    Style::default().fg(Color::Red),
+                ),
+            ]),
+            Spans::from(vec![
+                Span::raw("Time "),
+                Span::styled(format_frames(game.clock()), Style::default().fg(Color::Red)),
+            ]),
+            Spans::from(vec![Span::raw("")]),
+            Spans::from(vec![
+                Span::raw("Combo "),
+                Span::styled(
+                    game.combo.unwrap_or(0).to_string(),
+                    Style::default().fg(Color::Red),
+                ),
+                Span::raw("  B2B "),
+                Span::styled(
+                    game.back_to_back.unwrap_or(0).to_string(),
+                    Style::default().fg(Color::Red),
+                ),
+            ]),
         ];
-        let score_section = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Length(10), Constraint::Min(0)].as_ref())
-            .split(chunks[2]);
         let score_paragraph = Paragraph::new(text)
             .style(Style::default().fg(Color::White))
             .alignment(Alignment::Center)
@@ -893,7 +2229,23 @@ pub fn draw_game(
                     .borders(Borders::ALL),
             );
 
-        f.render_widget(score_paragraph, score_section[0]);
+        f.render_widget(score_paragraph, left_section[1]);
+
+        let lock_delay_ratio = game
+            .lock_delay_remaining()
+            .map(|remaining| remaining as f64 / game.lock_delay.max(1) as f64)
+            .unwrap_or(1.0);
+        let lock_delay_gauge = Gauge::default()
+            .block(
+                Block::default()
+                    .title("Lock")
+                    .title_alignment(Alignment::Center)
+                    .borders(Borders::ALL),
+            )
+            .gauge_style(Style::default().fg(Color::Red))
+            .label("")
+            .ratio(lock_delay_ratio.clamp(0.0, 1.0));
+        f.render_widget(lock_delay_gauge, left_section[2]);
     })?;
     Ok(())
 }
@@ -907,8 +2259,8 @@ pub fn draw_game_over(
             .direction(Direction::Vertical)
             .constraints(
                 [
-                    Constraint::Length(((game.playfield.len() + 2) / 3).try_into().unwrap()),
-                    Constraint::Length(((game.playfield.len() + 2) / 3).try_into().unwrap()),
+                    Constraint::Length((game.visible_height + 2).div_ceil(3).try_into().unwrap()),
+                    Constraint::Length((game.visible_height + 2).div_ceil(3).try_into().unwrap()),
                     Constraint::Min(0),
                 ]
                 .as_ref(),
@@ -919,34 +2271,974 @@ pub fn draw_game_over(
             .constraints(
                 [
                     Constraint::Length(20),
-                    Constraint::Length((game.playfield[0].len() * 2 + 2).try_into().unwrap()),
+                    Constraint::Length((game.width * 2 + 2).try_into().unwrap()),
                     Constraint::Min(0),
                 ]
                 .as_ref(),
             )
             .split(game_over_layout_v[1]);
-        let game_over_paragraph = Paragraph::new(vec![
-            Spans::from(""),
-            Spans::from(""),
-            Spans::from("Score"),
-            Spans::from(Span::styled(
-                game.score.to_string(),
+        let reason = game
+            .game_over
+            .map(|reason| reason.to_string())
+            .unwrap_or_default();
+        let title = match game.game_over {
+            Some(reason) if !reason.is_top_out() => format!("{} complete", game.mode),
+            _ => "Game over".to_string(),
+        };
+        // What the mode is about comes first
+        let results = match game.mode {
+            GameMode::Sprint => vec![
+                ("Time", format_frames(game.frame)),
+                ("Lines", game.lines_cleared.to_string()),
+            ],
+            GameMode::Dig => vec![
+                ("Time", format_frames(game.frame)),
+                ("Garbage", game.garbage_cleared.to_string()),
+            ],
+            GameMode::Ultra => vec![
+                ("Score", game.score.to_string()),
+                ("Lines", game.lines_cleared.to_string()),
+            ],
+            GameMode::Marathon | GameMode::Endless => vec![
+                ("Score", game.score.to_string()),
+                ("Level", game.level.to_string()),
+                ("Lines", game.lines_cleared.to_string()),
+                ("Time", format_frames(game.frame)),
+            ],
+        };
+        let mut text = vec![Spans::from(reason), Spans::from("")];
+        for (label, value) in results {
+            text.push(Spans::from(label));
+            text.push(Spans::from(Span::styled(
+                value,
                 Style::default().fg(Color::Red),
-            )),
-            Spans::from(""),
-            Spans::from("Press q to quit"),
-        ])
-        .style(Style::default().fg(Color::White))
-        .alignment(Alignment::Center)
-        .wrap(Wrap { trim: true })
-        .block(
-            Block::default()
-                .title("Game over")
-                .title_alignment(Alignment::Center)
-                .borders(Borders::ALL),
-        );
+            )));
+        }
+        text.push(Spans::from(""));
+        text.push(Spans::from("Press q to quit"));
+        let game_over_paragraph = Paragraph::new(text)
+            .style(Style::default().fg(Color::White))
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true })
+            .block(
+                Block::default()
+                    .title(title)
+                    .title_alignment(Alignment::Center)
+                    .borders(Borders::ALL),
+            );
 
         f.render_widget(game_over_paragraph, game_over_layout[1]);
     })?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with_pieces(pieces: &str) -> GameConfig {
+        GameConfig {
+            seed: 0,
+            randomizer: RandomizerKind::Sequence(parse_piece_sequence(pieces).unwrap()),
+            ..GameConfig::default()
+        }
+    }
+
+    fn game_with_pieces(pieces: &str) -> Game {
+        Game::with_config(config_with_pieces(pieces)).unwrap()
+    }
+
+    // Fill `rows` with garbage, all but the `holes` given as (row, column)
+    fn fill_rows_except(
+        game: &mut Game,
+        rows: std::ops::RangeInclusive<usize>,
+        holes: &[(usize, usize)],
+    ) {
+        for row in rows {
+            for column in 0..game.width {
+                if !holes.contains(&(row, column)) {
+                    game.playfield[row][column] = Cell::Garbage;
+                }
+            }
+        }
+    }
+
+    fn place_moving_piece(game: &mut Game, piece_type: PieceType, rotation_id: usize, at: Point) {
+        let mut piece = Piece::new(piece_type);
+        piece.current_rotation_id = rotation_id;
+        piece.center = at;
+        game.moving_piece = piece;
+        game.update_ghost_piece();
+    }
+
+    // The (row, column) of every mino of `piece`
+    fn cells(piece: &Piece) -> Vec<(i16, i16)> {
+        let points = piece.get_piece_points().unwrap();
+        points.iter().map(|point| (point.0, point.1)).collect()
+    }
+
+    fn leftmost_column(game: &Game) -> i16 {
+        let points = game.moving_piece.get_piece_points().unwrap();
+        points.iter().map(|point| point.1).min().unwrap()
+    }
+
+    fn pass_frames(game: &mut Game, frames: u64) {
+        for _ in 0..frames {
+            game.handle_event(Event::TimePassed);
+        }
+    }
+
+    #[test]
+    fn untouched_game_falls_and_tops_out() {
+        let mut game = Game::with_seed(1);
+        let spawn_row = game.moving_piece.center.0;
+        pass_frames(&mut game, 59);
+        assert_eq!(game.moving_piece.center.0, spawn_row);
+        pass_frames(&mut game, 2);
+        assert_eq!(game.moving_piece.center.0, spawn_row + 1);
+        assert_eq!(game.frame, 61);
+
+        while game.game_over.is_none() && game.frame < 100_000 {
+            game.handle_event(Event::TimePassed);
+        }
+        assert!(game.game_over.unwrap().is_top_out());
+        let frame = game.frame;
+        pass_frames(&mut game, 10);
+        assert_eq!(game.frame, frame);
+    }
+
+    #[test]
+    fn same_seed_plays_out_the_same_game() {
+        let mut first = Game::with_seed(7);
+        let mut second = Game::with_seed(7);
+        for game in [&mut first, &mut second] {
+            for _ in 0..20 {
+                game.handle_event(Event::HardDropPiece);
+                pass_frames(game, 1);
+            }
+        }
+        assert_eq!(first.playfield, second.playfield);
+        assert_eq!(first.upcoming_pieces(), second.upcoming_pieces());
+    }
+
+    #[test]
+    fn repeated_rotate_key_rotates_once_and_carries_over() {
+        let mut game = game_with_pieces("T");
+        let rotate = Button::Rotate(CLOCKWISE);
+        // What the input thread sends for a key that is tapped and then starts repeating
+        game.handle_event(Event::Press(rotate));
+        game.handle_event(Event::Release(rotate));
+        game.handle_event(Event::Repeat(rotate));
+        assert_eq!(game.moving_piece.current_rotation_id, 1);
+
+        game.handle_event(Event::HardDropPiece);
+        pass_frames(&mut game, 1);
+        assert_eq!(game.moving_piece.current_rotation_id, 1);
+    }
+
+    #[test]
+    fn double_tapped_rotate_key_rotates_twice_and_does_not_carry_over() {
+        let mut game = game_with_pieces("T");
+        let rotate = Button::Rotate(CLOCKWISE);
+        for _ in 0..2 {
+            game.handle_event(Event::Press(rotate));
+            game.handle_event(Event::Release(rotate));
+        }
+        assert_eq!(game.moving_piece.current_rotation_id, 2);
+
+        game.handle_event(Event::HardDropPiece);
+        pass_frames(&mut game, 1);
+        assert_eq!(game.moving_piece.current_rotation_id, 0);
+    }
+
+    #[test]
+    fn held_direction_waits_for_das_then_reaches_the_wall_with_instant_arr() {
+        let config = GameConfig {
+            das: 10,
+            arr: 0,
+            ..config_with_pieces("T")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        let spawn_column = leftmost_column(&game);
+        game.handle_event(Event::Press(Button::Left));
+        assert_eq!(leftmost_column(&game), spawn_column - 1);
+        pass_frames(&mut game, 9);
+        assert_eq!(leftmost_column(&game), spawn_column - 1);
+        pass_frames(&mut game, 1);
+        assert_eq!(leftmost_column(&game), 0);
+    }
+
+    #[test]
+    fn tapped_direction_moves_once_even_without_das_or_arr() {
+        let config = GameConfig {
+            das: 0,
+            arr: 0,
+            ..config_with_pieces("T")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        let spawn_column = leftmost_column(&game);
+        game.handle_event(Event::Press(Button::Left));
+        game.handle_event(Event::Release(Button::Left));
+        pass_frames(&mut game, 30);
+        assert_eq!(leftmost_column(&game), spawn_column - 1);
+    }
+
+    #[test]
+    fn repeated_direction_charges_das_without_moving_again() {
+        let config = GameConfig {
+            das: 10,
+            arr: 2,
+            ..config_with_pieces("T")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        let spawn_column = leftmost_column(&game);
+        game.handle_event(Event::Press(Button::Right));
+        game.handle_event(Event::Release(Button::Right));
+        game.handle_event(Event::Repeat(Button::Right));
+        assert_eq!(leftmost_column(&game), spawn_column + 1);
+        pass_frames(&mut game, 10);
+        assert_eq!(leftmost_column(&game), spawn_column + 2);
+        pass_frames(&mut game, 2);
+        assert_eq!(leftmost_column(&game), spawn_column + 3);
+        game.handle_event(Event::Release(Button::Right));
+        pass_frames(&mut game, 10);
+        assert_eq!(leftmost_column(&game), spawn_column + 3);
+    }
+
+    #[test]
+    fn das_counts_from_the_press_of_a_key_found_to_be_held_later() {
+        let config = GameConfig {
+            das: 10,
+            arr: 2,
+            ..config_with_pieces("T")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        let spawn_column = leftmost_column(&game);
+        game.handle_event(Event::Press(Button::Left));
+        game.handle_event(Event::Release(Button::Left));
+        // The terminal starts repeating the key well after DAS would have been charged
+        pass_frames(&mut game, 30);
+        game.handle_event(Event::Repeat(Button::Left));
+        pass_frames(&mut game, 1);
+        assert_eq!(leftmost_column(&game), spawn_column - 2);
+        pass_frames(&mut game, 2);
+        assert_eq!(leftmost_column(&game), spawn_column - 3);
+    }
+
+    #[test]
+    fn tapped_soft_drop_moves_one_cell() {
+        let mut game = game_with_pieces("T");
+        let spawn_row = game.moving_piece.center.0;
+        game.handle_event(Event::Press(Button::SoftDrop));
+        game.handle_event(Event::Release(Button::SoftDrop));
+        assert_eq!(game.moving_piece.center.0, spawn_row + 1);
+        assert_eq!(game.score, SOFT_DROP_POINTS_PER_CELL);
+    }
+
+    #[test]
+    fn hard_drop_scores_for_every_cell_dropped() {
+        let mut game = game_with_pieces("T");
+        // 9 rows above the floor, with nothing to clear when it lands
+        place_moving_piece(&mut game, PieceType::T, 0, Point(30, 4));
+        game.handle_event(Event::HardDropPiece);
+        assert_eq!(game.score, 9 * HARD_DROP_POINTS_PER_CELL);
+        assert_eq!(game.score, 18);
+    }
+
+    #[test]
+    fn entry_delay_holds_back_the_next_piece() {
+        let config = GameConfig {
+            entry_delay: 5,
+            ..config_with_pieces("TO")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        game.handle_event(Event::HardDropPiece);
+        assert_eq!(game.phase, Phase::EntryDelay(5));
+        pass_frames(&mut game, 4);
+        assert_eq!(game.phase, Phase::EntryDelay(1));
+        pass_frames(&mut game, 1);
+        assert_eq!(game.phase, Phase::Falling);
+        assert_eq!(game.moving_piece.piece_type, PieceType::O);
+    }
+
+    #[test]
+    fn nothing_moves_or_drops_without_a_piece_in_play() {
+        let config = GameConfig {
+            entry_delay: 5,
+            ..config_with_pieces("TO")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        game.hard_drop_moving_piece().unwrap();
+        let locked = cells(&game.moving_piece);
+        let score = game.score;
+        // Between pieces the locked piece is left alone, and a second drop does nothing
+        assert!(game.move_moving_piece(LEFT).is_err());
+        assert!(game.rotate_moving_piece(&CLOCKWISE).is_err());
+        assert!(game.soft_drop_moving_piece().is_err());
+        assert_eq!(game.hard_drop_moving_piece(), Ok(()));
+        assert_eq!(game.hold_moving_piece(), Ok(()));
+        assert_eq!(cells(&game.moving_piece), locked);
+        assert_eq!(game.score, score);
+        assert_eq!(game.held_piece.map(|piece| piece.piece_type), None);
+        // Nor once the game is over
+        pass_frames(&mut game, 5);
+        game.end_game(GameOverReason::TimeUp);
+        let score = game.score;
+        assert!(game.move_moving_piece(LEFT).is_err());
+        assert_eq!(game.hard_drop_moving_piece(), Ok(()));
+        assert_eq!(game.score, score);
+    }
+
+    #[test]
+    fn line_clear_delay_keeps_filled_rows_until_it_ends() {
+        let config = GameConfig {
+            entry_delay: 2,
+            line_clear_delay: 3,
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        // Everything but the 4 columns the I piece falls into
+        let bottom = game.playfield.len() - 1;
+        for column in [0, 1, 2, 7, 8, 9] {
+            game.playfield[bottom][column] = Cell::Garbage;
+        }
+        game.handle_event(Event::HardDropPiece);
+        assert_eq!(game.phase, Phase::LineClearDelay(3));
+        assert!(game.playfield[bottom].iter().all(|cell| !cell.is_empty()));
+        pass_frames(&mut game, 3);
+        assert_eq!(game.phase, Phase::EntryDelay(2));
+        assert!(game.playfield[bottom].iter().all(|cell| cell.is_empty()));
+        pass_frames(&mut game, 2);
+        assert_eq!(game.phase, Phase::Falling);
+    }
+
+    #[test]
+    fn hard_drop_during_entry_delay_drops_the_next_piece() {
+        let config = GameConfig {
+            entry_delay: 5,
+            ..config_with_pieces("TO")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        game.handle_event(Event::HardDropPiece);
+        pass_frames(&mut game, 2);
+        game.handle_event(Event::HardDropPiece);
+        pass_frames(&mut game, 3);
+        // The O piece locked the moment it spawned, and the T piece after it is on its way
+        assert_eq!(game.phase, Phase::EntryDelay(5));
+        let locked_o = game
+            .playfield
+            .iter()
+            .flatten()
+            .filter(|cell| **cell == Cell::Locked(PieceType::O))
+            .count();
+        assert_eq!(locked_o, 4);
+    }
+
+    // Lock an I piece into the 4 columns left open in the bottom row, clearing it
+    fn clear_bottom_row_with_i(game: &mut Game) {
+        let bottom = game.playfield.len() - 1;
+        for (column, cell) in game.playfield[bottom].iter_mut().enumerate() {
+            *cell = if (3..7).contains(&column) {
+                Cell::Empty
+            } else {
+                Cell::Garbage
+            };
+        }
+        game.update_ghost_piece();
+        assert_eq!(game.moving_piece.piece_type, PieceType::I);
+        game.handle_event(Event::HardDropPiece);
+    }
+
+    #[test]
+    fn sprint_ends_at_40_lines() {
+        let config = GameConfig {
+            mode: GameMode::Sprint,
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        for _ in 0..39 {
+            clear_bottom_row_with_i(&mut game);
+        }
+        assert_eq!(game.game_over, None);
+        assert_eq!(game.lines_to_goal(), 1);
+        clear_bottom_row_with_i(&mut game);
+        assert_eq!(game.game_over, Some(GameOverReason::GoalReached));
+        assert_eq!(game.level, 1);
+    }
+
+    #[test]
+    fn marathon_ends_at_150_lines_and_level_15_whatever_the_goal() {
+        for level_goal in [LevelGoal::Variable, LevelGoal::Fixed] {
+            let config = GameConfig {
+                level_goal,
+                ..config_with_pieces("I")
+            };
+            let mut game = Game::with_config(config).unwrap();
+            assert_eq!(game.level_goal, LevelGoal::Fixed);
+            for _ in 0..149 {
+                clear_bottom_row_with_i(&mut game);
+            }
+            assert_eq!(game.game_over, None);
+            assert_eq!(game.lines_to_goal(), 1);
+            clear_bottom_row_with_i(&mut game);
+            assert_eq!(game.game_over, Some(GameOverReason::GoalReached));
+            assert_eq!(game.level, MARATHON_LEVELS);
+        }
+    }
+
+    #[test]
+    fn ultra_ends_after_2_minutes() {
+        let config = GameConfig {
+            mode: GameMode::Ultra,
+            gravity_table: vec![0.0],
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        pass_frames(&mut game, ULTRA_FRAMES - 1);
+        assert_eq!(game.game_over, None);
+        assert_eq!(game.clock(), 1);
+        pass_frames(&mut game, 1);
+        assert_eq!(game.game_over, Some(GameOverReason::TimeUp));
+    }
+
+    #[test]
+    fn pausing_is_ignored_once_the_game_is_over() {
+        let config = GameConfig {
+            mode: GameMode::Ultra,
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        game.handle_event(Event::TogglePause);
+        assert!(game.paused);
+        game.handle_event(Event::TogglePause);
+        game.end_game(GameOverReason::TimeUp);
+        game.handle_event(Event::TogglePause);
+        assert!(!game.paused);
+    }
+
+    #[test]
+    fn dig_ends_once_its_garbage_is_cleared() {
+        let config = GameConfig {
+            mode: GameMode::Dig,
+            dig_lines: 1,
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        assert_eq!(game.lines_to_goal(), 1);
+        clear_bottom_row_with_i(&mut game);
+        assert_eq!(game.garbage_cleared, 1);
+        assert_eq!(game.game_over, Some(GameOverReason::GoalReached));
+    }
+
+    #[test]
+    fn garbage_pushing_the_stack_out_of_the_buffer_zone_tops_out() {
+        let config = GameConfig {
+            mode: GameMode::Dig,
+            dig_lines: 100,
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        let bottom = game.playfield.len() - 1;
+        game.playfield[bottom].fill(Cell::Empty);
+        game.playfield[0][0] = Cell::Locked(PieceType::I);
+        assert_eq!(game.add_garbage(), Err(GameOverReason::TopOut));
+        assert_eq!(game.game_over, Some(GameOverReason::TopOut));
+        assert_eq!(game.playfield[0][0], Cell::Locked(PieceType::I));
+    }
+
+    #[test]
+    fn t_piece_takes_the_last_kick_into_a_triple_slot() {
+        let mut game = game_with_pieces("T");
+        // The slot for a T pointing right with its center at (38, 3), under an overhang at (35, 3)
+        fill_rows_except(&mut game, 37..=39, &[(37, 3), (38, 3), (38, 4), (39, 3)]);
+        game.playfield[35][3] = Cell::Garbage;
+        place_moving_piece(&mut game, PieceType::T, 0, Point(36, 4));
+
+        game.handle_event(Event::RotatePiece(CLOCKWISE));
+        assert_eq!(game.last_rotation_kick, Some((CLOCKWISE, 4)));
+        assert_eq!(game.moving_piece.current_rotation_id, 1);
+        let center = game.moving_piece.center;
+        assert_eq!((center.0, center.1), (38, 3));
+    }
+
+    #[test]
+    fn i_piece_kicks_off_the_wall_by_two_columns() {
+        let mut game = game_with_pieces("I");
+        // Standing up against the left wall, where lying down in place would stick out of it
+        place_moving_piece(&mut game, PieceType::I, 1, Point(30, 0));
+
+        game.handle_event(Event::RotatePiece(CLOCKWISE));
+        assert_eq!(game.last_rotation_kick, Some((CLOCKWISE, 2)));
+        assert_eq!(game.moving_piece.current_rotation_id, 2);
+        assert_eq!(
+            cells(&game.moving_piece),
+            [(31, 0), (31, 1), (31, 2), (31, 3)]
+        );
+    }
+
+    #[test]
+    fn half_turn_flips_the_piece_and_kicks_it_up_off_the_floor() {
+        let mut game = game_with_pieces("T");
+        place_moving_piece(&mut game, PieceType::T, 0, Point(30, 4));
+        game.handle_event(Event::RotatePiece(HALF_TURN));
+        assert_eq!(game.last_rotation_kick, Some((HALF_TURN, 0)));
+        assert_eq!(game.moving_piece.current_rotation_id, 2);
+        assert_eq!(
+            cells(&game.moving_piece),
+            [(30, 3), (30, 4), (30, 5), (31, 4)]
+        );
+
+        // Flat on the floor, pointing down in place would stick out of it
+        place_moving_piece(&mut game, PieceType::T, 0, Point(39, 4));
+        game.handle_event(Event::RotatePiece(HALF_TURN));
+        assert_eq!(game.last_rotation_kick, Some((HALF_TURN, 1)));
+        assert_eq!(HALF_TURN_KICKS[0][1], (0, 1));
+        assert_eq!(game.moving_piece.current_rotation_id, 2);
+        assert_eq!(
+            cells(&game.moving_piece),
+            [(38, 3), (38, 4), (38, 5), (39, 4)]
+        );
+    }
+
+    #[test]
+    fn o_piece_never_kicks() {
+        let mut game = game_with_pieces("O");
+        let before = cells(&game.moving_piece);
+        game.handle_event(Event::RotatePiece(CLOCKWISE));
+        assert_eq!(game.last_rotation_kick, Some((CLOCKWISE, 0)));
+        assert_eq!(cells(&game.moving_piece), before);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected_instead_of_panicking() {
+        let invalid_configs = [
+            GameConfig {
+                width: 3,
+                ..GameConfig::default()
+            },
+            GameConfig {
+                height: 0,
+                ..GameConfig::default()
+            },
+            GameConfig {
+                randomizer: RandomizerKind::Sequence(Vec::new()),
+                ..GameConfig::default()
+            },
+            GameConfig {
+                gravity_table: Vec::new(),
+                ..GameConfig::default()
+            },
+            GameConfig {
+                preview: 0,
+                ..GameConfig::default()
+            },
+            GameConfig {
+                garbage_holes: PLAYFIELD_WIDTH,
+                ..GameConfig::default()
+            },
+        ];
+        for config in invalid_configs {
+            assert!(Game::with_config(config).is_err());
+        }
+    }
+
+    #[test]
+    fn playfield_follows_the_configured_size() {
+        let config = GameConfig {
+            width: 6,
+            height: 12,
+            ..GameConfig::default()
+        };
+        let game = Game::with_config(config).unwrap();
+        assert_eq!(game.playfield.len(), 12 + BUFFER_HEIGHT);
+        assert!(game.playfield.iter().all(|row| row.len() == 6));
+    }
+
+    #[test]
+    fn last_kick_upgrades_a_mini_t_spin_to_a_full_one() {
+        let mut game = game_with_pieces("T");
+        // The triple slot, with the lowest corner in front of the T left open
+        fill_rows_except(
+            &mut game,
+            37..=39,
+            &[(37, 3), (38, 3), (38, 4), (39, 3), (39, 4)],
+        );
+        game.playfield[35][3] = Cell::Garbage;
+        place_moving_piece(&mut game, PieceType::T, 0, Point(36, 4));
+
+        game.handle_event(Event::RotatePiece(CLOCKWISE));
+        assert_eq!(
+            game.last_rotation_kick,
+            Some((CLOCKWISE, T_SPIN_UPGRADE_KICK))
+        );
+        assert_eq!(game.detect_t_spin(), Some(TSpin::Full));
+        game.handle_event(Event::HardDropPiece);
+        let line_clear = game.last_clear.unwrap();
+        assert_eq!(
+            (line_clear.lines, line_clear.t_spin),
+            (2, Some(TSpin::Full))
+        );
+        assert_eq!(game.score, 1200);
+    }
+
+    #[test]
+    fn t_spin_with_one_front_corner_blocked_is_a_mini() {
+        let mut game = game_with_pieces("T");
+        fill_rows_except(&mut game, 39..=39, &[(39, 1)]);
+        game.playfield[37][0] = Cell::Garbage;
+        place_moving_piece(&mut game, PieceType::T, 0, Point(38, 1));
+
+        game.handle_event(Event::RotatePiece(CLOCKWISE));
+        assert_eq!(game.last_rotation_kick, Some((CLOCKWISE, 0)));
+        game.handle_event(Event::HardDropPiece);
+        let line_clear = game.last_clear.unwrap();
+        assert_eq!(
+            (line_clear.lines, line_clear.t_spin),
+            (1, Some(TSpin::Mini))
+        );
+        assert_eq!(game.score, 200);
+    }
+
+    #[test]
+    fn t_piece_dropped_into_a_slot_without_rotating_is_no_t_spin() {
+        let mut game = game_with_pieces("T");
+        fill_rows_except(&mut game, 38..=39, &[(38, 3), (38, 4), (38, 5), (39, 4)]);
+        place_moving_piece(&mut game, PieceType::T, 2, Point(30, 4));
+
+        game.handle_event(Event::HardDropPiece);
+        let line_clear = game.last_clear.unwrap();
+        assert_eq!((line_clear.lines, line_clear.t_spin), (2, None));
+    }
+
+    fn line_clear(lines: usize, t_spin: Option<TSpin>) -> LineClear {
+        LineClear {
+            lines,
+            t_spin,
+            perfect_clear: false,
+        }
+    }
+
+    #[test]
+    fn back_to_back_clears_score_half_again_and_easy_clears_break_the_chain() {
+        let mut game = game_with_pieces("I");
+        game.adjust_score(&line_clear(4, None));
+        assert_eq!((game.score, game.back_to_back), (800, Some(0)));
+        game.adjust_score(&line_clear(2, Some(TSpin::Full)));
+        assert_eq!(game.back_to_back, Some(1));
+        assert_eq!(game.score, 800 + 1800 + COMBO_POINTS);
+        // A T-spin that clears nothing neither keeps nor breaks the chain
+        game.adjust_score(&line_clear(0, Some(TSpin::Full)));
+        assert_eq!(game.back_to_back, Some(1));
+        game.adjust_score(&line_clear(1, None));
+        assert_eq!(game.back_to_back, None);
+    }
+
+    #[test]
+    fn combo_grows_with_every_clearing_lock_and_ends_on_a_lock_that_clears_nothing() {
+        let mut game = game_with_pieces("I");
+        for combo in 0..3 {
+            let score = game.score;
+            game.adjust_score(&line_clear(1, None));
+            assert_eq!(game.combo, Some(combo));
+            assert_eq!(game.score - score, 100 + COMBO_POINTS * combo as u64);
+        }
+        game.adjust_score(&line_clear(0, None));
+        assert_eq!(game.combo, None);
+    }
+
+    #[test]
+    fn chain_bonuses_scale_with_the_level() {
+        let mut game = game_with_pieces("I");
+        game.level = 3;
+        game.adjust_score(&line_clear(4, None));
+        game.adjust_score(&line_clear(4, None));
+        assert_eq!(game.score, 3 * (800 + 1200 + COMBO_POINTS));
+    }
+
+    #[test]
+    fn perfect_clear_bonus_follows_the_guideline() {
+        let bonuses: Vec<_> = (1..=4)
+            .map(|lines| line_clear(lines, None).perfect_clear_bonus(false))
+            .collect();
+        assert_eq!(bonuses, [800, 1200, 1800, 2000]);
+        assert_eq!(line_clear(4, None).perfect_clear_bonus(true), 3200);
+    }
+
+    #[test]
+    fn clearing_the_whole_playfield_is_a_perfect_clear() {
+        let mut game = game_with_pieces("I");
+        clear_bottom_row_with_i(&mut game);
+        assert!(game.last_clear.unwrap().perfect_clear);
+        assert_eq!(game.perfect_clears, 1);
+
+        // A single mino left over the cleared row spoils it
+        let bottom = game.playfield.len() - 1;
+        game.playfield[bottom - 1][0] = Cell::Garbage;
+        clear_bottom_row_with_i(&mut game);
+        assert_eq!(game.last_clear.unwrap().lines, 1);
+        assert!(!game.last_clear.unwrap().perfect_clear);
+        assert_eq!(game.perfect_clears, 1);
+    }
+
+    #[test]
+    fn back_to_back_tetris_perfect_clear_earns_the_bigger_bonus() {
+        let mut game = game_with_pieces("I");
+        game.adjust_score(&line_clear(4, None));
+        let score = game.score;
+        game.adjust_score(&LineClear {
+            perfect_clear: true,
+            ..line_clear(4, None)
+        });
+        assert_eq!(game.score - score, 1200 + 3200 + COMBO_POINTS);
+    }
+
+    #[test]
+    fn variable_goal_awards_more_lines_for_bigger_clears() {
+        let config = GameConfig {
+            mode: GameMode::Endless,
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        assert_eq!(game.level_goal_total(), 5);
+        game.adjust_level(&line_clear(4, None));
+        // A tetris is worth 8 goal lines, 3 of which carry over to level 2
+        assert_eq!((game.level, game.goal_lines, game.lines_cleared), (2, 3, 4));
+        assert_eq!(game.lines_to_next_level(), 7);
+        game.adjust_level(&line_clear(1, None));
+        assert_eq!((game.level, game.goal_lines, game.lines_cleared), (2, 4, 5));
+    }
+
+    #[test]
+    fn variable_goal_awards_t_spins_and_back_to_back_clears() {
+        let config = GameConfig {
+            mode: GameMode::Endless,
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        let t_spins = [
+            (line_clear(0, Some(TSpin::Mini)), 1),
+            (line_clear(1, Some(TSpin::Mini)), 2),
+            (line_clear(0, Some(TSpin::Full)), 4),
+            (line_clear(1, Some(TSpin::Full)), 8),
+            (line_clear(2, Some(TSpin::Full)), 12),
+            (line_clear(3, Some(TSpin::Full)), 16),
+        ];
+        for (clear, expected) in t_spins {
+            assert_eq!(clear.goal_lines(), expected);
+        }
+        // A T-spin double after a tetris continues the back-to-back chain, for 12 * 1.5 lines
+        game.adjust_level(&line_clear(4, None));
+        game.adjust_score(&line_clear(4, None));
+        let goal_lines = game.goal_lines;
+        game.adjust_level(&line_clear(2, Some(TSpin::Full)));
+        assert_eq!(game.level, 3);
+        assert_eq!(game.goal_lines, goal_lines + 18 - 10);
+        assert_eq!(game.lines_cleared, 6);
+    }
+
+    #[test]
+    fn fixed_goal_counts_every_line_once() {
+        let config = GameConfig {
+            level_goal: LevelGoal::Fixed,
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        for _ in 0..9 {
+            clear_bottom_row_with_i(&mut game);
+        }
+        assert_eq!((game.level, game.lines_to_goal()), (1, 1));
+        clear_bottom_row_with_i(&mut game);
+        assert_eq!(
+            (game.level, game.goal_lines, game.lines_cleared),
+            (2, 0, 10)
+        );
+        assert_eq!(game.lines_to_goal(), FIXED_GOAL_LINES);
+    }
+
+    #[test]
+    fn counters_saturate_instead_of_overflowing() {
+        let mut game = game_with_pieces("I");
+        game.score = u64::MAX - 1;
+        game.lines_cleared = u32::MAX;
+        game.adjust_score(&line_clear(4, None));
+        game.adjust_level(&line_clear(4, None));
+        assert_eq!(game.score, u64::MAX);
+        assert_eq!(game.lines_cleared, u32::MAX);
+    }
+
+    #[test]
+    fn guideline_gravity_starts_at_a_cell_per_second_and_speeds_up() {
+        let table = guideline_gravity_table();
+        assert_eq!(table.len(), 20);
+        assert!((table[0] - 1.0 / 60.0).abs() < 1e-9);
+        assert!(table.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(table[19], INSTANT_GRAVITY);
+    }
+
+    #[test]
+    fn gravity_table_parses_fractions_and_g_suffixes() {
+        assert_eq!(
+            parse_gravity_table("1/60, 0.5,1G,20g").unwrap(),
+            [1.0 / 60.0, 0.5, 1.0, 20.0]
+        );
+        for invalid in ["", "fast", "-1", "1/0", "1/2/3"] {
+            assert!(parse_gravity_table(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn levels_past_the_table_keep_its_last_gravity() {
+        let config = GameConfig {
+            gravity_table: vec![0.1, 0.2],
+            ..config_with_pieces("I")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        assert_eq!(game.gravity(), 0.1);
+        game.level = 30;
+        assert_eq!(game.gravity(), 0.2);
+    }
+
+    #[test]
+    fn gravity_moves_whole_cells_per_frame_up_to_instant() {
+        let config = GameConfig {
+            gravity_table: vec![1.0, INSTANT_GRAVITY],
+            ..config_with_pieces("O")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        let spawn_row = game.moving_piece.center.0;
+        pass_frames(&mut game, 3);
+        assert_eq!(game.moving_piece.center.0, spawn_row + 3);
+
+        game.level = 2;
+        pass_frames(&mut game, 1);
+        assert_eq!(cells(&game.moving_piece), cells(&game.ghost_piece.unwrap()));
+    }
+
+    #[test]
+    fn preview_shows_the_configured_number_of_pieces_after_the_current_one() {
+        let config = GameConfig {
+            preview: 3,
+            ..config_with_pieces("IJLOSTZ")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        assert_eq!(game.moving_piece.piece_type, PieceType::I);
+        assert_eq!(
+            game.upcoming_pieces(),
+            [PieceType::J, PieceType::L, PieceType::O]
+        );
+        game.handle_event(Event::HardDropPiece);
+        assert_eq!(game.moving_piece.piece_type, PieceType::J);
+        assert_eq!(
+            game.upcoming_pieces(),
+            [PieceType::L, PieceType::O, PieceType::S]
+        );
+    }
+
+    // Stand an I piece up on the floor and keep turning it, which lifts it off the ground every
+    // other turn, until it locks
+    fn frames_until_a_turning_piece_locks(policy: LockDelayPolicy) -> u64 {
+        let config = GameConfig {
+            lock_delay_policy: policy,
+            ..config_with_pieces("IO")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        place_moving_piece(&mut game, PieceType::I, 1, Point(37, 4));
+        while game.moving_piece.piece_type == PieceType::I && game.frame < 2000 {
+            if game.frame.is_multiple_of(20) {
+                game.handle_event(Event::RotatePiece(CLOCKWISE));
+            }
+            game.handle_event(Event::TimePassed);
+        }
+        assert_eq!(game.moving_piece.piece_type, PieceType::O, "{:?}", policy);
+        game.frame
+    }
+
+    #[test]
+    fn no_lock_delay_policy_lets_a_piece_stall_forever() {
+        for policy in [
+            LockDelayPolicy::MoveReset,
+            LockDelayPolicy::StepReset,
+            LockDelayPolicy::NoReset,
+        ] {
+            assert!(frames_until_a_turning_piece_locks(policy) < 2000);
+        }
+    }
+
+    // Shuffle a piece resting on the floor left and right every `interval` frames until it locks
+    fn shuffle_until_locked(policy: LockDelayPolicy, interval: u64) -> Game {
+        let config = GameConfig {
+            lock_delay_policy: policy,
+            ..config_with_pieces("OT")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        place_moving_piece(&mut game, PieceType::O, 0, Point(39, 4));
+        let mut direction = LEFT;
+        while game.moving_piece.piece_type == PieceType::O && game.frame < 2000 {
+            if game.frame.is_multiple_of(interval) {
+                game.handle_event(Event::MovePiece(direction));
+                direction = direction.opposite_direction();
+            }
+            game.handle_event(Event::TimePassed);
+        }
+        game
+    }
+
+    #[test]
+    fn move_reset_restarts_the_delay_until_it_runs_out_of_resets() {
+        let game = shuffle_until_locked(LockDelayPolicy::MoveReset, 10);
+        // Every move but the first restarts the delay, and the one using up the last reset locks
+        // the piece right away, before the frame it came in passes
+        assert_eq!(game.frame, 10 * MOVE_RESET_LIMIT as u64 + 1);
+    }
+
+    #[test]
+    fn step_and_no_reset_ignore_sideways_moves() {
+        for policy in [LockDelayPolicy::StepReset, LockDelayPolicy::NoReset] {
+            let game = shuffle_until_locked(policy, 10);
+            assert_eq!(game.frame, LOCK_DELAY as u64, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn step_reset_restarts_the_delay_on_a_lower_row() {
+        let config = GameConfig {
+            lock_delay_policy: LockDelayPolicy::StepReset,
+            ..config_with_pieces("OT")
+        };
+        let mut game = Game::with_config(config).unwrap();
+        // Resting on a ledge one row above the floor, next to a drop
+        game.playfield[39][3] = Cell::Garbage;
+        game.playfield[39][4] = Cell::Garbage;
+        place_moving_piece(&mut game, PieceType::O, 0, Point(38, 4));
+        pass_frames(&mut game, 20);
+        assert_eq!(game.lock_delay_remaining(), Some(LOCK_DELAY - 20));
+        game.handle_event(Event::MovePiece(RIGHT));
+        game.handle_event(Event::MovePiece(RIGHT));
+        game.handle_event(Event::MovePiece(DOWN));
+        assert_eq!(game.lock_delay_remaining(), Some(LOCK_DELAY));
+    }
+
+    #[test]
+    fn garbage_fits_in_short_playfields() {
+        for height in [2, 8, 12, 20] {
+            let config = GameConfig {
+                mode: GameMode::Dig,
+                height,
+                ..config_with_pieces("I")
+            };
+            let game = Game::with_config(config).unwrap();
+            let garbage_rows = game
+                .playfield
+                .iter()
+                .filter(|row| row.contains(&Cell::Garbage))
+                .count();
+            assert_eq!(garbage_rows, GARBAGE_HEIGHT.min(height / 2));
+        }
+        let config = GameConfig {
+            mode: GameMode::Dig,
+            height: 1,
+            ..GameConfig::default()
+        };
+        assert!(Game::with_config(config).is_err());
+    }
+}
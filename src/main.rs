@@ -1,109 +1,181 @@
 use blockstorm::{
-    draw_game, draw_game_over, Event, Game, MinoesError, OutOfBoundsError, OverlappingMinoesError,
-    DOWN,
-};
-use std::{
-    io,
-    time::{Duration, Instant},
+    draw_game, draw_game_over, parse_gravity_table, parse_piece_sequence, DrawOptions, Event, Game,
+    GameConfig, RandomizerKind,
 };
+use std::{env, io, process};
 use termion::raw::IntoRawMode;
 use tui::{backend::TermionBackend, Terminal};
 
-const LOCK_DELAY: Duration = Duration::from_millis(500);
+const USAGE: &str =
+    "Usage: blockstorm [--mode <marathon|sprint|ultra|endless|dig>] [--seed <number>] \
+[--randomizer <7bag|14bag|random|nes|tgm|tgm3>] [--sequence <pieces, e.g. IJLOSTZ>] [--gray-stack] \
+[--peek <hidden rows to show>] \
+[--width <columns>] [--height <visible rows>] [--partial-lock-out] \
+[--lock-delay <frames>] [--lock-reset <move|step|classic>] \
+[--goal <variable|fixed, marathon is always fixed>] \
+[--gravity <cells per frame for each level, e.g. 1/60,0.5,1G,20G>] \
+[--das <frames>] [--arr <frames>] [--sdf <soft drop factor>] [--preview <1-6 pieces>] \
+[--entry-delay <frames>] [--line-clear-delay <frames>] \
+[--dig-lines <rows>] [--garbage-holes <holes per row>] [--messiness <0-1>]";
+
+fn parse_args() -> Result<(Game, DrawOptions), String> {
+    let mut config = GameConfig::default();
+    let mut draw_options = DrawOptions::default();
+    let mut raw_args = env::args().skip(1);
+    while let Some(arg) = raw_args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                let value = raw_args.next().ok_or("--mode needs a value")?;
+                config.mode = value.parse()?;
+            }
+            "--dig-lines" => {
+                let value = raw_args.next().ok_or("--dig-lines needs a value")?;
+                config.dig_lines = value
+                    .parse()
+                    .map_err(|_| format!("Invalid number of rows: {}", value))?;
+            }
+            "--garbage-holes" => {
+                let value = raw_args.next().ok_or("--garbage-holes needs a value")?;
+                config.garbage_holes = value
+                    .parse()
+                    .map_err(|_| format!("Invalid number of holes: {}", value))?;
+            }
+            "--messiness" => {
+                let value = raw_args.next().ok_or("--messiness needs a value")?;
+                config.messiness = value
+                    .parse()
+                    .map_err(|_| format!("Invalid messiness: {}", value))?;
+            }
+            "--seed" => {
+                let value = raw_args.next().ok_or("--seed needs a value")?;
+                let seed = value
+                    .parse()
+                    .map_err(|_| format!("Invalid seed: {}", value))?;
+                config.seed = seed;
+            }
+            "--randomizer" => {
+                let value = raw_args.next().ok_or("--randomizer needs a value")?;
+                config.randomizer = value.parse()?;
+            }
+            "--sequence" => {
+                let value = raw_args.next().ok_or("--sequence needs a value")?;
+                config.randomizer = RandomizerKind::Sequence(parse_piece_sequence(&value)?);
+            }
+            "--peek" => {
+                let value = raw_args.next().ok_or("--peek needs a value")?;
+                draw_options.buffer_peek = value
+                    .parse()
+                    .map_err(|_| format!("Invalid number of rows: {}", value))?;
+            }
+            "--width" => {
+                let value = raw_args.next().ok_or("--width needs a value")?;
+                config.width = value
+                    .parse()
+                    .map_err(|_| format!("Invalid width: {}", value))?;
+            }
+            "--height" => {
+                let value = raw_args.next().ok_or("--height needs a value")?;
+                config.height = value
+                    .parse()
+                    .map_err(|_| format!("Invalid height: {}", value))?;
+            }
+            "--partial-lock-out" => {
+                config.partial_lock_out = true;
+            }
+            "--lock-delay" => {
+                let value = raw_args.next().ok_or("--lock-delay needs a value")?;
+                config.lock_delay = value
+                    .parse()
+                    .map_err(|_| format!("Invalid lock delay: {}", value))?;
+            }
+            "--lock-reset" => {
+                let value = raw_args.next().ok_or("--lock-reset needs a value")?;
+                config.lock_delay_policy = value.parse()?;
+            }
+            "--goal" => {
+                let value = raw_args.next().ok_or("--goal needs a value")?;
+                config.level_goal = value.parse()?;
+            }
+            "--gravity" => {
+                let value = raw_args.next().ok_or("--gravity needs a value")?;
+                config.gravity_table = parse_gravity_table(&value)?;
+            }
+            "--das" => {
+                let value = raw_args.next().ok_or("--das needs a value")?;
+                config.das = value
+                    .parse()
+                    .map_err(|_| format!("Invalid DAS: {}", value))?;
+            }
+            "--arr" => {
+                let value = raw_args.next().ok_or("--arr needs a value")?;
+                config.arr = value
+                    .parse()
+                    .map_err(|_| format!("Invalid ARR: {}", value))?;
+            }
+            "--sdf" => {
+                let value = raw_args.next().ok_or("--sdf needs a value")?;
+                config.soft_drop_factor = value
+                    .parse()
+                    .map_err(|_| format!("Invalid soft drop factor: {}", value))?;
+            }
+            "--preview" => {
+                let value = raw_args.next().ok_or("--preview needs a value")?;
+                config.preview = value
+                    .parse()
+                    .map_err(|_| format!("Invalid number of pieces: {}", value))?;
+            }
+            "--entry-delay" => {
+                let value = raw_args.next().ok_or("--entry-delay needs a value")?;
+                config.entry_delay = value
+                    .parse()
+                    .map_err(|_| format!("Invalid entry delay: {}", value))?;
+            }
+            "--line-clear-delay" => {
+                let value = raw_args.next().ok_or("--line-clear-delay needs a value")?;
+                config.line_clear_delay = value
+                    .parse()
+                    .map_err(|_| format!("Invalid line clear delay: {}", value))?;
+            }
+            "--gray-stack" => {
+                draw_options.gray_stack = true;
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+    Ok((Game::with_config(config)?, draw_options))
+}
 
 fn main() {
-    let mut game = Game::default();
-    let mut game_ended = false;
+    let (mut game, draw_options) = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(1);
+    });
+    game.start_threads();
 
     let stdout = io::stdout().into_raw_mode().unwrap();
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend).unwrap();
     terminal.clear().unwrap();
-    let mut last_piece_move = Instant::now();
-    let mut game_paused = false;
 
     loop {
         let event = game.event_receiver.recv().unwrap();
-        match event {
-            Event::TimePassed => {
-                if !game_ended && !game_paused {
-                    match game.move_moving_piece(DOWN) {
-                        Ok(_) => (),
-                        Err(OutOfBoundsError) => {
-                            let delay_passed = Instant::now() - last_piece_move > LOCK_DELAY;
-                            if delay_passed {
-                                last_piece_move = Instant::now();
-                                match game.hard_drop_moving_piece() {
-                                    Ok(_) => (),
-                                    Err(MinoesError::OverlappingMinoes(OverlappingMinoesError)) => {
-                                        draw_game_over(&mut terminal, &mut game).unwrap();
-                                        game_ended = true;
-                                    }
-                                    Err(_) => panic!("Unexpected error"),
-                                }
-                            }
-                        }
-                    };
-                }
-            }
-            Event::Quit => {
-                terminal.clear().unwrap();
-                break;
-            }
-            Event::MovePiece(direction) => {
-                if !game_ended {
-                    match direction {
-                        blockstorm::TetrisDirection::Down => {}
-                        _ => {
-                            last_piece_move = Instant::now();
-                        }
-                    }
-                    match game.move_moving_piece(direction) {
-                        Ok(_) => (),
-                        Err(_) => (),
-                    };
-                }
-            }
-            Event::RotatePiece(rotation) => {
-                if !game_ended {
-                    last_piece_move = Instant::now();
-                    match game.rotate_moving_piece(&rotation) {
-                        Ok(_) => (),
-                        Err(_) => (),
-                    };
-                }
-            }
-            Event::HardDropPiece => {
-                if !game_ended {
-                    last_piece_move = Instant::now();
-                    match game.hard_drop_moving_piece() {
-                        Ok(_) => (),
-                        Err(MinoesError::OverlappingMinoes(OverlappingMinoesError)) => {
-                            draw_game_over(&mut terminal, &mut game).unwrap();
-                            game_ended = true;
-                        }
-                        Err(_) => panic!("Unexpected error"),
-                    };
-                }
-            }
-            Event::HoldPiece => {
-                last_piece_move = Instant::now();
-                if !game_ended {
-                    if !game.hold_used {
-                        match game.hold_moving_piece() {
-                            Ok(_) => (),
-                            Err(_) => (),
-                        };
-                    }
-                }
-            }
-            Event::TogglePause => {
-                game_paused = !game_paused;
-            }
+        if let Event::Quit = event {
+            terminal.clear().unwrap();
+            break;
         }
-        if !game_ended {
-            draw_game(&mut terminal, &mut game).unwrap();
+        game.handle_event(event);
+        if game.game_over.is_some() {
+            draw_game_over(&mut terminal, &mut game).unwrap();
+        } else {
+            draw_game(&mut terminal, &mut game, &draw_options).unwrap();
         }
     }
     terminal.show_cursor().unwrap();
+    // Leave raw mode before printing, so the seed can be used to replay the game
+    drop(terminal);
+    println!("Seed: {}", game.seed);
 }
@@ -0,0 +1,450 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+use crate::PieceType;
+
+const ALL_PIECE_TYPES: [PieceType; 7] = [
+    PieceType::I,
+    PieceType::J,
+    PieceType::L,
+    PieceType::O,
+    PieceType::S,
+    PieceType::T,
+    PieceType::Z,
+];
+
+// Pieces the TGM games allow as the very first piece, so a game never starts with an overhang
+const TGM_FIRST_PIECES: [PieceType; 4] = [PieceType::I, PieceType::J, PieceType::L, PieceType::T];
+
+pub trait Randomizer: fmt::Debug + Send {
+    fn next_piece_type(&mut self) -> PieceType;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RandomizerKind {
+    SevenBag,
+    FourteenBag,
+    Random,
+    Nes,
+    TgmHistory,
+    Tgm3,
+    Sequence(Vec<PieceType>),
+}
+
+impl RandomizerKind {
+    pub fn build(&self, seed: u64) -> Box<dyn Randomizer> {
+        match self {
+            RandomizerKind::SevenBag => Box::new(BagRandomizer::new(seed, 1)),
+            RandomizerKind::FourteenBag => Box::new(BagRandomizer::new(seed, 2)),
+            RandomizerKind::Random => Box::new(PureRandomizer::new(seed)),
+            RandomizerKind::Nes => Box::new(NesRandomizer::new(seed)),
+            RandomizerKind::TgmHistory => Box::new(TgmHistoryRandomizer::new(seed)),
+            RandomizerKind::Tgm3 => Box::new(Tgm3Randomizer::new(seed)),
+            RandomizerKind::Sequence(pieces) => Box::new(SequenceRandomizer::new(pieces.clone())),
+        }
+    }
+}
+
+impl FromStr for RandomizerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "7bag" => Ok(RandomizerKind::SevenBag),
+            "14bag" => Ok(RandomizerKind::FourteenBag),
+            "random" => Ok(RandomizerKind::Random),
+            "nes" => Ok(RandomizerKind::Nes),
+            "tgm" => Ok(RandomizerKind::TgmHistory),
+            "tgm3" => Ok(RandomizerKind::Tgm3),
+            _ => Err(format!("Unknown randomizer: {}", s)),
+        }
+    }
+}
+
+// Parse a sequence of pieces such as "IJLOSTZ"
+pub fn parse_piece_sequence(s: &str) -> Result<Vec<PieceType>, String> {
+    let pieces = s
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'I' => Ok(PieceType::I),
+            'J' => Ok(PieceType::J),
+            'L' => Ok(PieceType::L),
+            'O' => Ok(PieceType::O),
+            'S' => Ok(PieceType::S),
+            'T' => Ok(PieceType::T),
+            'Z' => Ok(PieceType::Z),
+            _ => Err(format!("Unknown piece: {}", c)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if pieces.is_empty() {
+        return Err("The piece sequence is empty".to_string());
+    }
+    Ok(pieces)
+}
+
+// Deals out shuffled bags holding `copies` of every piece
+#[derive(Debug)]
+pub struct BagRandomizer {
+    rng: StdRng,
+    copies: usize,
+    bag: Vec<PieceType>,
+}
+
+impl BagRandomizer {
+    pub fn new(seed: u64, copies: usize) -> BagRandomizer {
+        BagRandomizer {
+            rng: StdRng::seed_from_u64(seed),
+            copies,
+            bag: Vec::new(),
+        }
+    }
+}
+
+impl Randomizer for BagRandomizer {
+    fn next_piece_type(&mut self) -> PieceType {
+        if self.bag.is_empty() {
+            for _ in 0..self.copies {
+                self.bag.extend_from_slice(&ALL_PIECE_TYPES);
+            }
+            self.bag.shuffle(&mut self.rng);
+        }
+        self.bag.pop().unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub struct PureRandomizer {
+    rng: StdRng,
+}
+
+impl PureRandomizer {
+    pub fn new(seed: u64) -> PureRandomizer {
+        PureRandomizer {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Randomizer for PureRandomizer {
+    fn next_piece_type(&mut self) -> PieceType {
+        *ALL_PIECE_TYPES.choose(&mut self.rng).unwrap()
+    }
+}
+
+// Rolls an 8-sided die where the 8th side means "reroll". Rolling the dummy side or the previous
+// piece rerolls once with a 7-sided die, whose result is kept no matter what.
+#[derive(Debug)]
+pub struct NesRandomizer {
+    rng: StdRng,
+    previous: Option<PieceType>,
+}
+
+impl NesRandomizer {
+    pub fn new(seed: u64) -> NesRandomizer {
+        NesRandomizer {
+            rng: StdRng::seed_from_u64(seed),
+            previous: None,
+        }
+    }
+}
+
+impl Randomizer for NesRandomizer {
+    fn next_piece_type(&mut self) -> PieceType {
+        let roll = self.rng.gen_range(0..ALL_PIECE_TYPES.len() + 1);
+        let mut piece_type = ALL_PIECE_TYPES.get(roll).copied();
+        if piece_type.is_none() || piece_type == self.previous {
+            piece_type = ALL_PIECE_TYPES.choose(&mut self.rng).copied();
+        }
+        self.previous = piece_type;
+        piece_type.unwrap()
+    }
+}
+
+// The TGM2 randomizer: keeps the last 4 pieces and rerolls up to 6 times while the roll is one of
+// them
+#[derive(Debug)]
+pub struct TgmHistoryRandomizer {
+    rng: StdRng,
+    history: VecDeque<PieceType>,
+    first_piece: bool,
+}
+
+impl TgmHistoryRandomizer {
+    const ROLLS: usize = 6;
+
+    pub fn new(seed: u64) -> TgmHistoryRandomizer {
+        TgmHistoryRandomizer {
+            rng: StdRng::seed_from_u64(seed),
+            history: VecDeque::from([PieceType::Z, PieceType::S, PieceType::S, PieceType::Z]),
+            first_piece: true,
+        }
+    }
+}
+
+impl Randomizer for TgmHistoryRandomizer {
+    fn next_piece_type(&mut self) -> PieceType {
+        let piece_type = if self.first_piece {
+            self.first_piece = false;
+            *TGM_FIRST_PIECES.choose(&mut self.rng).unwrap()
+        } else {
+            let mut piece_type = *ALL_PIECE_TYPES.choose(&mut self.rng).unwrap();
+            for _ in 1..Self::ROLLS {
+                if !self.history.contains(&piece_type) {
+                    break;
+                }
+                piece_type = *ALL_PIECE_TYPES.choose(&mut self.rng).unwrap();
+            }
+            piece_type
+        };
+        self.history.pop_front();
+        self.history.push_back(piece_type);
+        piece_type
+    }
+}
+
+// The TGM3 randomizer: rolls from a pool of 35 pieces, 5 of each. Every time a piece is picked,
+// or rerolled because it is in the 4 piece history, its slot in the pool is given to the piece
+// that has not been seen for the longest time, which makes droughts much shorter.
+#[derive(Debug)]
+pub struct Tgm3Randomizer {
+    rng: StdRng,
+    pool: Vec<PieceType>,
+    history: VecDeque<PieceType>,
+    // Pieces from the longest unseen to the most recently seen
+    drought_order: Vec<PieceType>,
+    first_piece: bool,
+}
+
+impl Tgm3Randomizer {
+    const ROLLS: usize = 6;
+
+    pub fn new(seed: u64) -> Tgm3Randomizer {
+        Tgm3Randomizer {
+            rng: StdRng::seed_from_u64(seed),
+            pool: ALL_PIECE_TYPES
+                .iter()
+                .flat_map(|piece_type| [*piece_type; 5])
+                .collect(),
+            history: VecDeque::from([PieceType::S, PieceType::Z, PieceType::S, PieceType::Z]),
+            drought_order: vec![
+                PieceType::J,
+                PieceType::I,
+                PieceType::Z,
+                PieceType::L,
+                PieceType::O,
+                PieceType::T,
+                PieceType::S,
+            ],
+            first_piece: true,
+        }
+    }
+}
+
+impl Randomizer for Tgm3Randomizer {
+    fn next_piece_type(&mut self) -> PieceType {
+        let piece_type = if self.first_piece {
+            self.first_piece = false;
+            *TGM_FIRST_PIECES.choose(&mut self.rng).unwrap()
+        } else {
+            let mut index = 0;
+            for roll in 1..=Self::ROLLS {
+                index = self.rng.gen_range(0..self.pool.len());
+                if !self.history.contains(&self.pool[index]) || roll == Self::ROLLS {
+                    break;
+                }
+                self.pool[index] = self.drought_order[0];
+            }
+            let piece_type = self.pool[index];
+            self.drought_order
+                .retain(|droughted| *droughted != piece_type);
+            self.drought_order.push(piece_type);
+            self.pool[index] = self.drought_order[0];
+            piece_type
+        };
+        self.history.pop_front();
+        self.history.push_back(piece_type);
+        piece_type
+    }
+}
+
+// Repeats a fixed sequence of pieces forever
+#[derive(Debug)]
+pub struct SequenceRandomizer {
+    pieces: Vec<PieceType>,
+    next_index: usize,
+}
+
+impl SequenceRandomizer {
+    pub fn new(pieces: Vec<PieceType>) -> SequenceRandomizer {
+        assert!(!pieces.is_empty(), "The piece sequence is empty");
+        SequenceRandomizer {
+            pieces,
+            next_index: 0,
+        }
+    }
+}
+
+impl Randomizer for SequenceRandomizer {
+    fn next_piece_type(&mut self) -> PieceType {
+        let piece_type = self.pieces[self.next_index];
+        self.next_index = (self.next_index + 1) % self.pieces.len();
+        piece_type
+    }
+}
+
+// The upcoming pieces, generated by a randomizer ahead of time so that the queue always holds at
+// least `length` of them
+#[derive(Debug)]
+pub struct PieceQueue {
+    randomizer: Box<dyn Randomizer>,
+    pieces: VecDeque<PieceType>,
+    length: usize,
+}
+
+impl PieceQueue {
+    pub fn new(randomizer: Box<dyn Randomizer>, length: usize) -> PieceQueue {
+        let mut queue = PieceQueue {
+            randomizer,
+            pieces: VecDeque::new(),
+            length: length.max(1),
+        };
+        queue.fill();
+        queue
+    }
+
+    fn fill(&mut self) {
+        while self.pieces.len() < self.length {
+            let piece_type = self.randomizer.next_piece_type();
+            self.pieces.push_back(piece_type);
+        }
+    }
+
+    pub fn peek(&self) -> PieceType {
+        self.pieces[0]
+    }
+
+    pub fn pop(&mut self) -> PieceType {
+        let piece_type = self.pieces.pop_front().unwrap();
+        self.fill();
+        piece_type
+    }
+
+    // The next `count` pieces, up to the length of the queue
+    pub fn upcoming(&self, count: usize) -> impl Iterator<Item = PieceType> + '_ {
+        self.pieces.iter().copied().take(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deal(randomizer: &mut dyn Randomizer, count: usize) -> Vec<PieceType> {
+        (0..count).map(|_| randomizer.next_piece_type()).collect()
+    }
+
+    fn count_of(pieces: &[PieceType], piece_type: PieceType) -> usize {
+        pieces.iter().filter(|piece| **piece == piece_type).count()
+    }
+
+    #[test]
+    fn seven_bag_deals_every_piece_once_per_bag() {
+        let mut randomizer = BagRandomizer::new(3, 1);
+        for _ in 0..20 {
+            let bag = deal(&mut randomizer, 7);
+            for piece_type in ALL_PIECE_TYPES {
+                assert_eq!(count_of(&bag, piece_type), 1, "{:?}", bag);
+            }
+        }
+    }
+
+    #[test]
+    fn fourteen_bag_deals_every_piece_twice_per_bag() {
+        let mut randomizer = BagRandomizer::new(3, 2);
+        for _ in 0..20 {
+            let bag = deal(&mut randomizer, 14);
+            for piece_type in ALL_PIECE_TYPES {
+                assert_eq!(count_of(&bag, piece_type), 2, "{:?}", bag);
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_deals_the_same_pieces() {
+        for kind in [
+            RandomizerKind::SevenBag,
+            RandomizerKind::FourteenBag,
+            RandomizerKind::Random,
+            RandomizerKind::Nes,
+            RandomizerKind::TgmHistory,
+            RandomizerKind::Tgm3,
+        ] {
+            let first = deal(kind.build(42).as_mut(), 100);
+            let second = deal(kind.build(42).as_mut(), 100);
+            assert_eq!(first, second, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn tgm_randomizers_never_start_with_an_overhang() {
+        for seed in 0..100 {
+            for kind in [RandomizerKind::TgmHistory, RandomizerKind::Tgm3] {
+                let first_piece = kind.build(seed).next_piece_type();
+                assert!(TGM_FIRST_PIECES.contains(&first_piece), "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn tgm_history_rarely_repeats_recent_pieces() {
+        let pieces = deal(&mut TgmHistoryRandomizer::new(5), 1000);
+        let repeats = pieces.windows(2).filter(|pair| pair[0] == pair[1]).count();
+        // A pure random randomizer repeats about once every 7 pieces
+        assert!(repeats < 20, "{} repeats", repeats);
+    }
+
+    #[test]
+    fn sequence_repeats_forever() {
+        let pieces = parse_piece_sequence("ts").unwrap();
+        let mut randomizer = SequenceRandomizer::new(pieces);
+        assert_eq!(
+            deal(&mut randomizer, 5),
+            [
+                PieceType::T,
+                PieceType::S,
+                PieceType::T,
+                PieceType::S,
+                PieceType::T
+            ]
+        );
+    }
+
+    #[test]
+    fn piece_sequence_must_hold_known_pieces() {
+        assert!(parse_piece_sequence("").is_err());
+        assert!(parse_piece_sequence("IJX").is_err());
+    }
+
+    #[test]
+    fn queue_stays_filled_ahead_as_pieces_are_taken() {
+        let pieces = parse_piece_sequence("IJLOSTZ").unwrap();
+        let mut queue = PieceQueue::new(Box::new(SequenceRandomizer::new(pieces)), 6);
+        assert_eq!(queue.pieces.len(), 6);
+        assert_eq!(queue.peek(), PieceType::I);
+        assert_eq!(queue.pop(), PieceType::I);
+        assert_eq!(queue.pieces.len(), 6);
+        assert_eq!(
+            queue.upcoming(6).collect::<Vec<_>>(),
+            [
+                PieceType::J,
+                PieceType::L,
+                PieceType::O,
+                PieceType::S,
+                PieceType::T,
+                PieceType::Z
+            ]
+        );
+        assert_eq!(queue.upcoming(2).count(), 2);
+        assert_eq!(queue.upcoming(10).count(), 6);
+    }
+}